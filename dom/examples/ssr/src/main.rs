@@ -30,8 +30,8 @@ struct PathExtractor {
 #[topo::nested]
 fn simple_list(items: &[String]) -> Ul {
     let mut list = ul();
-    for item in items {
-        list = list.child(mox!(<li>{ item }</li>));
+    for item in keyed(items, |item| item.to_string(), |item| mox!(<li>{ item }</li>)) {
+        list = list.child(item);
     }
     list.build()
 }
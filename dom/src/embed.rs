@@ -5,7 +5,7 @@ use futures::{
     future::LocalFutureObj,
     task::{LocalSpawn, SpawnError},
 };
-use moxie::runtime::RunLoop;
+use moxie::runtime::{RunLoop, Unstable};
 
 /// Wrapper around `moxie::runtime::RunLoop` and a root function which returns a
 /// DOM node. After each call to `run_once` the node returned from the root
@@ -49,6 +49,25 @@ impl DomLoop {
     pub fn run_once(&mut self) {
         self.inner.run_once();
     }
+
+    /// Sets the executor that will be used to spawn tasks, such as a
+    /// `LocalPool` when rendering on the server.
+    pub fn set_task_executor(&mut self, sp: impl LocalSpawn + 'static) {
+        self.inner.set_task_executor(sp);
+    }
+
+    /// Run revisions until one completes without any state changes, calling
+    /// `run_tasks` after each to drive the loop's executor, and binding the
+    /// final node as the child of the loop's parent. Useful for rendering a
+    /// complete page on the server. See
+    /// [`moxie::runtime::RunLoop::run_until_stable`].
+    pub fn run_until_stable(
+        &mut self,
+        max_revisions: u64,
+        run_tasks: impl FnMut(),
+    ) -> Result<(), Unstable> {
+        self.inner.run_until_stable(max_revisions, run_tasks)
+    }
 }
 
 #[cfg(feature = "rsdom")]
@@ -18,7 +18,7 @@ pub mod prelude {
     #[cfg(feature = "webdom")]
     pub use crate::raw::sys;
     pub use crate::raw::{document, event, Dom as RawDom, Node as RawNode};
-    pub use moxie::{cache, cache_state, cache_with, once, once_with, state, Key};
+    pub use moxie::{cache, cache_state, cache_with, keyed, once, once_with, state, Key};
 
     pub use crate::{
         elements::html,
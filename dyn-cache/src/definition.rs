@@ -202,6 +202,29 @@ Call [`" stringify!($cache) "::get`] to get a [`CacheMiss`] and [`CacheMiss::ini
         self.inner.values_mut().for_each(|namespace| namespace.sweep());
         self.revision += 1;
     }
+
+doc_comment! {"
+Discard the liveness marks made by reads and writes since the last call to
+[`" stringify!($cache) "::gc`], so that the next call only retains values which are used after
+this call. This includes values stored since the last GC.
+
+Useful when the work done since the last GC was abandoned, for example because it panicked.
+"=>
+    pub fn rollback(&mut self) {
+        self.inner.values_mut().for_each(|namespace| namespace.rollback());
+    }}
+
+    /// Returns the hits, misses, and evictions of each namespace with any
+    /// activity since the last call to this method, and resets them.
+    pub fn take_stats(&mut self) -> Vec<NamespaceStats> {
+        let mut stats = self.inner
+            .values_mut()
+            .map(|namespace| namespace.take_stats())
+            .filter(|ns| !ns.is_empty())
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|ns| (ns.scope, ns.input, ns.output));
+        stats
+    }
 }
 
 impl std::panic::UnwindSafe for $cache {}
@@ -301,6 +324,8 @@ See [`" stringify!($shared) "::cache`] for an ergonomic wrapper that requires `O
             Ok(stored) => return with(stored),
             Err(m) => m,
         };
+        #[cfg(feature = "tracing")]
+        tracing::trace!(output = std::any::type_name::<Output>(), "cache miss");
 
         let (to_store, to_return) = miss.init(|arg| {
             let store = init(arg);
@@ -365,6 +390,20 @@ Forwards to [`" stringify!($cache) "::gc`].
         self.inner.$acquire().gc();
     }}
 
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::rollback`].
+"=>
+    pub fn rollback(&self) {
+        self.inner.$acquire().rollback();
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::take_stats`].
+"=>
+    pub fn take_stats(&self) -> Vec<NamespaceStats> {
+        self.inner.$acquire().take_stats()
+    }}
+
     fn addr(&self) -> usize {
         $refct::as_ptr(&self.inner) as *const _ as _
     }
@@ -551,6 +590,51 @@ mod $test_mod {
         assert_counts!(1, 1); // prior GC had no accesses, should be dropped
     }
 
+    #[test]
+    fn stats_count_hits_misses_and_evictions() {
+        let storage = $shared::default();
+        storage.cache(&'a', &1, |&n| n);
+        storage.cache(&'a', &1, |&n| n);
+        storage.cache(&'a', &2, |&n| n);
+        storage.cache(&'b', &1u8, |&n| n);
+
+        let stats = storage.take_stats();
+        assert_eq!(stats.len(), 2, "one namespace per input type");
+        let ints = stats.iter().find(|ns| ns.input_type() == "i32").unwrap();
+        assert_eq!((ints.hits(), ints.misses(), ints.evictions()), (1, 2, 0));
+        assert!(storage.take_stats().is_empty(), "counts reset when taken");
+
+        storage.gc();
+        storage.cache(&'a', &2, |&n| n);
+        storage.gc();
+        let stats = storage.take_stats();
+        let ints = stats.iter().find(|ns| ns.input_type() == "i32").unwrap();
+        let bytes = stats.iter().find(|ns| ns.input_type() == "u8").unwrap();
+        assert_eq!((ints.hits(), ints.misses(), ints.evictions()), (1, 0, 0));
+        assert_eq!((bytes.hits(), bytes.misses(), bytes.evictions()), (0, 0, 1));
+    }
+
+    #[test]
+    fn rollback_discards_liveness_marks() {
+        let storage = $shared::default();
+        let call_count = std::cell::Cell::new(0);
+        let increment_count = |&to_add: &i32| {
+            call_count.set(call_count.get() + to_add);
+            call_count.get()
+        };
+
+        storage.cache_with(&'a', &1, &increment_count, Clone::clone);
+        storage.gc();
+
+        // read 'a' in work which is then abandoned
+        storage.cache_with(&'a', &1, &increment_count, Clone::clone);
+        storage.rollback();
+        storage.gc();
+
+        storage.cache_with(&'a', &1, &increment_count, Clone::clone);
+        assert_eq!(call_count.get(), 2, "'a' was dropped despite the abandoned read");
+    }
+
     struct CountDrops {
         num_drops: Arc<AtomicU32>,
     }
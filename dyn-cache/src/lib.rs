@@ -217,6 +217,12 @@
 //!
 //! Outside of these, only user-defined functions should perform any allocation.
 //!
+//! # Tracing
+//!
+//! With the `tracing` feature enabled, the shared caches emit a `TRACE`-level
+//! [`tracing`](https://docs.rs/tracing) event named `cache miss` with the
+//! output's type name each time a query has to be initialized.
+//!
 //! # Garbage Collection
 //!
 //! All of the caches have a `gc()` method which retains only used values. A
@@ -414,6 +420,55 @@ pub struct CacheEntry<'k, Key: ?Sized, Scope, Input, Output, H = DefaultHashBuil
     output: Output,
 }
 
+/// Counts of a cache's activity within one namespace, the storage for a single
+/// combination of scope, input, and output types. Returned by
+/// [`local::LocalCache::take_stats`] and [`sync::SendCache::take_stats`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct NamespaceStats {
+    scope: &'static str,
+    input: &'static str,
+    output: &'static str,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl NamespaceStats {
+    /// The name of the namespace's scope type.
+    pub fn scope_type(&self) -> &'static str {
+        self.scope
+    }
+
+    /// The name of the namespace's input type.
+    pub fn input_type(&self) -> &'static str {
+        self.input
+    }
+
+    /// The name of the namespace's output type.
+    pub fn output_type(&self) -> &'static str {
+        self.output
+    }
+
+    /// The number of reads which returned a stored output.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// The number of outputs stored after a failed read.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// The number of values dropped by garbage collection.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    fn is_empty(&self) -> bool {
+        self.hits == 0 && self.misses == 0 && self.evictions == 0
+    }
+}
+
 /// A cache for types which are not thread-safe (`?Send`).
 pub mod local {
     use std::{cell::RefCell, rc::Rc};
@@ -436,6 +491,13 @@ trait Storage: Downcast + Debug {
 
     /// Remove dead entries.
     fn sweep(&mut self);
+
+    /// Mark all entries dead, as they were after the last sweep.
+    fn rollback(&mut self);
+
+    /// Return the counts of reads, writes, and removals since the last call,
+    /// resetting them.
+    fn take_stats(&mut self) -> NamespaceStats;
 }
 
 impl_downcast!(Storage);
@@ -1,7 +1,7 @@
 use super::{
     cache_cell::CacheCell,
     dep_node::{DepNode, Dependent},
-    Storage,
+    NamespaceStats, Storage,
 };
 use hashbrown::{
     hash_map::{DefaultHashBuilder, RawEntryMut},
@@ -11,6 +11,7 @@ use hashbrown::{
 use std::{
     any::type_name,
     borrow::Borrow,
+    cell::Cell,
     fmt::{Debug, Formatter, Result as FmtResult},
     hash::{BuildHasher, Hash, Hasher},
     marker::PhantomData,
@@ -83,6 +84,10 @@ where
 #[derive(Clone)]
 pub(crate) struct Namespace<Scope, Input, Output, H = DefaultHashBuilder> {
     inner: HashMap<Scope, CacheCell<Input, Output>, H>,
+    /// Counted through a shared reference because reads don't borrow mutably.
+    hits: Cell<u64>,
+    misses: u64,
+    evictions: u64,
 }
 
 impl<Scope, Input, Output, H> Default for Namespace<Scope, Input, Output, H>
@@ -90,7 +95,7 @@ where
     H: Default,
 {
     fn default() -> Self {
-        Self { inner: Default::default() }
+        Self { inner: Default::default(), hits: Cell::new(0), misses: 0, evictions: 0 }
     }
 }
 
@@ -147,7 +152,11 @@ where
     {
         let hashed = self.hashed(key);
         if let Some((_, cell)) = self.entry(&hashed) {
-            cell.get(arg, dependent).map_err(|d| KeyMiss::hashed(hashed, arg.to_owned(), None, d))
+            let output = cell
+                .get(arg, dependent)
+                .map_err(|d| KeyMiss::hashed(hashed, arg.to_owned(), None, d))?;
+            self.hits.set(self.hits.get() + 1);
+            Ok(output)
         } else {
             let node = DepNode::new(dependent, revision);
             let new_dep = node.as_dependent();
@@ -160,6 +169,7 @@ where
         Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
         Scope: Borrow<Key>,
     {
+        self.misses += 1;
         let dependent = miss.dependent;
         let hashed = miss.inner.unwrap_or_else(|k| self.hashed(k));
         match self.entry_mut(&hashed) {
@@ -193,11 +203,31 @@ where
     }
 
     fn sweep(&mut self) {
+        let before = self.inner.len();
         self.inner.retain(|_, c| {
             let keep = c.is_live();
             c.mark_dead();
             keep
         });
+        self.evictions += (before - self.inner.len()) as u64;
+    }
+
+    fn rollback(&mut self) {
+        self.inner.values_mut().for_each(|c| c.mark_dead());
+    }
+
+    fn take_stats(&mut self) -> NamespaceStats {
+        let stats = NamespaceStats {
+            scope: type_name::<Scope>(),
+            input: type_name::<Input>(),
+            output: type_name::<Output>(),
+            hits: self.hits.replace(0),
+            misses: self.misses,
+            evictions: self.evictions,
+        };
+        self.misses = 0;
+        self.evictions = 0;
+        stats
     }
 }
 
@@ -33,13 +33,58 @@
 //! [`runtime::Revision`]s at the same callsite and are dropped from the cache
 //! at the end of the first revision where they were not used.
 //!
+//! Lists of items whose cached values should follow each item, rather than its
+//! position, can be iterated with [`keyed`].
+//!
+//! Values can be offered to a whole subtree with [`provide`] and read within it
+//! with [`consume`]. Unlike values offered with [`illicit`] directly, cached
+//! closures which consume a provided value re-run when it changes.
+//!
 //! ## State
 //!
 //! State variables are stored in the cache and can be mutated in between
 //! revisions. They are declared with the [`cache_state`] and [`state`]
 //! functions which return a [`Commit`] for reading the current value and a
-//! [`Key`] for updating it. Updates to state variables wake the runtime,
-//! initiating a new revision.
+//! [`Key`] for updating it. A [`KeyLens`] updates part of a state variable,
+//! like one field of a struct. Updates to state variables wake the runtime,
+//! initiating a new revision. Updates to several state variables can be made
+//! visible together with [`transaction`]. State variables whose updates are
+//! described by typed actions can be declared with [`reducer_state`], and
+//! those whose commits can be undone with [`history_state`]. State variables
+//! which are fed by other threads can be declared with [`channel_state`].
+//! State which is shared by the whole runtime, rather than declared at a
+//! callsite, can be read with [`store`].
+//!
+//! Values computed from state variables can be cached with [`derived`], which
+//! only re-runs its closure when one of the state variables it read has a new
+//! commit.
+//!
+//! ## Panics
+//!
+//! Panics within a subtree can be caught with [`catch_boundary`], which renders
+//! a fallback instead and keeps the subtree's cached values until it recovers.
+//!
+//! ## Effects
+//!
+//! Side effects which need to be cleaned up, like registering a listener or
+//! opening a connection, are declared with [`effect`]. Effects run after each
+//! revision's root function returns and their cleanups run once their
+//! dependencies change or their callsite is no longer called.
+//!
+//! ## Tracing
+//!
+//! With the `tracing-spans` cargo feature enabled, the runtime reports its work
+//! to [`tracing`](https://docs.rs/tracing) subscribers. Each revision runs
+//! within a `run_once` span and each `#[topo::nested]` function within a `call`
+//! span recording its callsite and slot. Cache misses and commits to state
+//! variables are recorded as `TRACE`-level events within those spans.
+//!
+//! ## Timers
+//!
+//! Revisions can be scheduled after a delay with [`timeout`] and at regular
+//! intervals with [`interval`], using the [`runtime::Clock`] provided by the
+//! embedding. [`testing::TestLoop`] provides a clock which tests can advance by
+//! hand.
 //!
 //! ## Loading Futures
 //!
@@ -48,7 +93,12 @@
 //! future is spawned to an async executor and return its status on every
 //! revision. When the future has completed, `Poll::Ready` is returned on
 //! each revision. If a revision occurs without referencing the pending future,
-//! the task is cancelled.
+//! the task is cancelled. Futures can be spawned onto executors for more or
+//! less urgent work with [`load_with_priority`], and [`load_with_policy`] can
+//! retry failures, time out, and keep showing stale values while reloading.
+//!
+//! Streams can be subscribed to with [`stream_latest`], which returns the most
+//! recent item and cancels the stream in the same way.
 //!
 //! [moxie-dom]: https://docs.rs/moxie-dom
 //! [topo]: https://docs.rs/topo/
@@ -57,18 +107,23 @@
 #![deny(clippy::all, missing_docs)]
 
 pub mod runtime;
+pub mod sync;
 pub mod testing;
 
-use crate::runtime::{Context, Var};
+use crate::runtime::{Context, Priority, Var};
+use futures::Stream;
 use parking_lot::Mutex;
 use std::{
+    any::Any,
     borrow::Borrow,
     fmt::{Debug, Display, Formatter, Result as FmtResult},
     future::Future,
     hash::{Hash, Hasher},
     ops::Deref,
-    sync::Arc,
+    rc::Rc,
+    sync::{Arc, Weak},
     task::Poll,
+    time::Duration,
 };
 use topo::CallId;
 
@@ -134,7 +189,7 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.cache.cache_with(&CallId::current(), arg, init, with)
+    rt.cache_with(&CallId::current(), arg, init, with)
 }
 
 /// Caches `init` once in the current [`topo::CallId`]. Runs `with` on every
@@ -175,7 +230,7 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.cache.cache_with(&CallId::current(), &(), |&()| init(), with)
+    rt.cache_with(&CallId::current(), &(), |&()| init(), with)
 }
 
 /// Memoizes `init` at this callsite, cloning a cached `Output` if it exists and
@@ -227,7 +282,7 @@ where
     Input: Borrow<Arg> + 'static,
     Output: Clone + 'static,
 {
-    rt.cache.cache(&CallId::current(), arg, init)
+    rt.cache_with(&CallId::current(), arg, init, Clone::clone)
 }
 
 /// Runs `init` once per [`topo::CallId`]. The provided value
@@ -261,7 +316,73 @@ pub fn once<Output>(init: impl FnOnce() -> Output) -> Output
 where
     Output: Clone + 'static,
 {
-    rt.cache.cache(&CallId::current(), &(), |()| init())
+    rt.cache_with(&CallId::current(), &(), |()| init(), Clone::clone)
+}
+
+/// Calls `body` with each of `items` in a [`topo::call_in_slot`] whose slot is
+/// the item's key, returning the results.
+///
+/// Values cached and state declared within `body` follow an item's key rather
+/// than its position, so they're retained when items are inserted, removed, or
+/// reordered. Each item should have a distinct key.
+///
+/// # Errors
+///
+/// In debug builds, each item whose key is equal to an earlier item's is
+/// reported with a `tracing` warning and called in a slot which also counts
+/// the earlier items with that key, so that they don't share state. Release
+/// builds skip this check and such items share cached values and state.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{keyed, runtime::Runtime, state};
+///
+/// let mut rt = Runtime::new();
+/// let names = |names: &[&'static str]| {
+///     keyed(names.iter().copied(), |name| *name, |name| state(|| name).1)
+/// };
+///
+/// let first = rt.run_once(|| names(&["alice", "bob"]));
+/// let second = rt.run_once(|| names(&["carol", "bob", "alice"]));
+/// assert_eq!(first[0], second[2], "alice's state moved with her");
+/// assert_eq!(first[1], second[1]);
+/// ```
+#[topo::nested]
+pub fn keyed<Item, Slot, Ret>(
+    items: impl IntoIterator<Item = Item>,
+    mut key: impl FnMut(&Item) -> Slot,
+    mut body: impl FnMut(Item) -> Ret,
+) -> Vec<Ret>
+where
+    Slot: Clone + Eq + Hash + Send + 'static,
+{
+    #[cfg(debug_assertions)]
+    let mut occurrences = std::collections::HashMap::new();
+
+    let mut results = Vec::new();
+    for item in items {
+        let slot = key(&item);
+
+        #[cfg(debug_assertions)]
+        {
+            let occurrence = occurrences.entry(slot.clone()).or_insert(0usize);
+            *occurrence += 1;
+            if *occurrence > 1 {
+                tracing::warn!(
+                    key = std::any::type_name::<Slot>(),
+                    occurrence = *occurrence,
+                    "keyed() was called with items whose keys were equal",
+                );
+                let slot = (slot, *occurrence);
+                results.push(topo::call_in_slot(&slot, || body(item)));
+                continue;
+            }
+        }
+
+        results.push(topo::call_in_slot(&slot, || body(item)));
+    }
+    results
 }
 
 /// Root a state variable at this callsite, returning a [`Key`] to the state
@@ -383,249 +504,882 @@ where
     rt.cache_state(&CallId::current(), arg, init)
 }
 
-/// Load a value from the future returned by `init` whenever `capture` changes,
-/// returning the result of calling `with` with the loaded value. Cancels the
-/// running future after any revision during which this call was not made.
+/// Root a state variable at this callsite which is updated by dispatching
+/// actions, returning a [`Commit`] of its current value and a [`Dispatch`]
+/// handle for sending it actions.
+///
+/// Each dispatched action is passed to `reduce` with the variable's latest
+/// value. If `reduce` returns `Some`, the new value is committed in the same way
+/// as [`Key::update`]. Keeping all of a variable's transitions in `reduce`
+/// allows them to be tested without a runtime, and actions can be logged or
+/// replayed by sending them through the same [`Dispatch`].
 ///
 /// # Example
 ///
 /// ```
-/// use futures::{channel::oneshot, executor::LocalPool};
-/// use moxie::{load_with, runtime::RunLoop};
-/// use std::{
-///     sync::{
-///         atomic::{AtomicU64, Ordering},
-///         mpsc::channel,
-///     },
-///     task::Poll,
-/// };
+/// use moxie::{reducer_state, runtime::RunLoop};
 ///
-/// let epoch = AtomicU64::new(0);
-/// let (send_futs, recv_futs) = channel();
+/// #[derive(Debug)]
+/// enum Action {
+///     Add(&'static str),
+///     Clear,
+/// }
 ///
-/// let mut rt = RunLoop::new(|| {
-///     // loads a new future when epoch changes
-///     load_with(
-///         &epoch.load(Ordering::Relaxed),
-///         |_| {
-///             let (sender, receiver) = oneshot::channel();
-///             send_futs.send(sender).unwrap();
-///             receiver
-///         },
-///         // makes this equivalent to load(...)
-///         |res| res.clone(),
-///     )
-/// });
+/// fn reduce(todos: &Vec<&'static str>, action: Action) -> Option<Vec<&'static str>> {
+///     match action {
+///         Action::Add(todo) => Some(todos.iter().copied().chain(Some(todo)).collect()),
+///         Action::Clear if todos.is_empty() => None,
+///         Action::Clear => Some(Vec::new()),
+///     }
+/// }
 ///
-/// let mut exec = LocalPool::new();
-/// rt.set_task_executor(exec.spawner());
+/// let mut rt = RunLoop::new(|| reducer_state(Vec::new, reduce));
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run_until_stalled();
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// let (todos, dispatch) = rt.run_once();
+/// assert!(todos.is_empty());
 ///
-/// // resolve the future
-/// let sender = recv_futs.recv().unwrap();
-/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
+/// dispatch.dispatch(Action::Add("write docs"));
+/// dispatch.dispatch(Action::Add("write tests"));
+/// let (todos, dispatch) = rt.run_once();
+/// assert_eq!(*todos, ["write docs", "write tests"]);
 ///
-/// sender.send(()).unwrap();
+/// dispatch.dispatch(Action::Clear);
+/// assert!(rt.run_once().0.is_empty());
+/// ```
+#[topo::nested]
+pub fn reducer_state<State, Action>(
+    init: impl FnOnce() -> State,
+    reduce: fn(&State, Action) -> Option<State>,
+) -> (Commit<State>, Dispatch<Action>)
+where
+    State: 'static,
+    Action: 'static,
+{
+    let (commit, key) = state(init);
+    let id = key.id();
+    let dispatch = Rc::new(move |action| key.update(|prev| reduce(prev, action)));
+    (commit, Dispatch { id, dispatch })
+}
+
+/// Root a state variable at this callsite which remembers up to `capacity` of
+/// its previous values, returning a [`HistoryKey`] which can undo and redo
+/// commits.
 ///
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// All commits made through the key before the state variable is next rooted
+/// are coalesced into a single step, so that a burst of updates from one event
+/// can be undone at once. The history holds the previous [`Commit`]s rather
+/// than copies of their values. If `capacity` shrinks between revisions, the
+/// oldest steps are dropped.
 ///
-/// // force the future to be reinitialized
-/// epoch.store(1, Ordering::Relaxed);
+/// # Example
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// ```
+/// use moxie::{history_state, runtime::RunLoop};
 ///
-/// // resolve the future
-/// let sender = recv_futs.recv().unwrap();
-/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
+/// let mut rt = RunLoop::new(|| history_state(|| 0, 100));
 ///
-/// sender.send(()).unwrap();
+/// let (_, count) = rt.run_once();
+/// count.set(1);
+/// let (_, count) = rt.run_once();
+/// count.set(2);
+/// count.set(3); // made before the next revision, undone along with the previous commit
 ///
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// let (current, count) = rt.run_once();
+/// assert_eq!(*current, 3);
+///
+/// assert!(count.undo());
+/// assert_eq!(*rt.run_once().0, 1);
+/// assert!(count.can_redo());
+///
+/// assert!(count.redo());
+/// assert_eq!(*rt.run_once().0, 3);
 /// ```
 #[topo::nested]
-#[illicit::from_env(rt: &Context)]
-pub fn load_with<Arg, Input, Fut, Output, Ret>(
-    arg: &Arg,
-    init: impl FnOnce(&Input) -> Fut,
-    with: impl FnOnce(&Output) -> Ret,
-) -> Poll<Ret>
+pub fn history_state<State>(
+    init: impl FnOnce() -> State,
+    capacity: usize,
+) -> (Commit<State>, HistoryKey<State>)
 where
-    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
-    Input: Borrow<Arg> + 'static,
-    Fut: Future<Output = Output> + 'static,
-    Output: 'static,
-    Ret: 'static,
+    State: 'static,
 {
-    rt.load_with(&CallId::current(), arg, init, with)
+    let (commit, key) = state(init);
+    let undo = once(|| runtime::UndoStack::new(capacity));
+    undo.lock().set_capacity(capacity);
+    (commit, HistoryKey { key, undo })
 }
 
-/// Calls [`load_with`] but never re-initializes the loading future.
+/// Root a state variable at this callsite, returning a [`Sender`] which
+/// commits values to it from any thread.
+///
+/// Each value sent enqueues a commit and wakes the runtime, like
+/// [`Key::update`]. Once the callsite is garbage collected the state variable is
+/// dropped and later sends fail, so work feeding it can stop.
 ///
 /// # Example
 ///
 /// ```
-/// use futures::{channel::oneshot, executor::LocalPool};
-/// use moxie::{load_once_with, runtime::RunLoop};
-/// use std::task::Poll;
-///
-/// let (sender, receiver) = oneshot::channel();
-/// let mut receiver = Some(receiver);
-/// let mut rt = RunLoop::new(|| load_once_with(|| receiver.take().unwrap(), |res| res.clone()));
+/// use moxie::{channel_state, runtime::RunLoop};
+/// use std::{cell::Cell, rc::Rc, thread};
 ///
-/// let mut exec = LocalPool::new();
-/// rt.set_task_executor(exec.spawner());
+/// let live = Rc::new(Cell::new(true));
+/// let root_live = live.clone();
+/// let mut rt = RunLoop::new(move || if root_live.get() { Some(channel_state(|| 0)) } else { None });
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run_until_stalled();
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// let (first, sender) = rt.run_once().unwrap();
+/// assert_eq!(*first, 0);
 ///
-/// sender.send(()).unwrap();
+/// let worker = sender.clone();
+/// thread::spawn(move || worker.send(1).unwrap()).join().unwrap();
+/// assert_eq!(*rt.run_once().unwrap().0, 1);
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// live.set(false);
+/// rt.run_once();
+/// assert_eq!(sender.send(2), Err(2), "the state variable was collected");
 /// ```
 #[topo::nested]
-#[illicit::from_env(rt: &Context)]
-pub fn load_once_with<Fut, Output, Ret>(
-    init: impl FnOnce() -> Fut,
-    with: impl FnOnce(&Output) -> Ret,
-) -> Poll<Ret>
+pub fn channel_state<State>(init: impl FnOnce() -> State) -> (Commit<State>, Sender<State>)
 where
-    Fut: Future<Output = Output> + 'static,
-    Output: 'static,
-    Ret: 'static,
+    State: Send + Sync + 'static,
 {
-    rt.load_with(&CallId::current(), &(), |()| init(), with)
+    let (commit, key) = state(init);
+    (commit, Sender { id: key.id, var: Arc::downgrade(&key.var) })
 }
 
-/// Calls [`load_with`], never re-initializes the loading future, and clones the
-/// returned value on each revision once the future has completed and returned.
+/// Returns the runtime-wide state variable of type `State`, which is created
+/// with `State::default()` the first time it's requested and shared by every
+/// caller in the runtime, regardless of callsite.
+///
+/// Stores are owned by the [`runtime::Runtime`] rather than its cache, so they
+/// live as long as the runtime does even if they aren't read during a revision.
+/// Otherwise they behave like any other state variable: updates through their
+/// [`Key`]s wake the runtime and reads within [`derived`] are tracked.
+///
+/// Each type has its own store. When state is persisted, stores are identified
+/// by the name of their type, so types whose names are equal, such as the same
+/// type from two versions of a crate, restore each other's values.
 ///
 /// # Example
 ///
 /// ```
-/// use futures::{channel::oneshot, executor::LocalPool};
-/// use moxie::{load_once, runtime::RunLoop};
-/// use std::task::Poll;
+/// use moxie::{runtime::RunLoop, store, Key};
 ///
-/// let (sender, receiver) = oneshot::channel();
-/// let mut receiver = Some(receiver);
-/// let mut rt = RunLoop::new(|| load_once(|| receiver.take().unwrap()));
+/// #[derive(Default, PartialEq)]
+/// struct Theme {
+///     dark: bool,
+/// }
 ///
-/// let mut exec = LocalPool::new();
-/// rt.set_task_executor(exec.spawner());
+/// fn toggle() -> Key<Theme> {
+///     store::<Theme>().1
+/// }
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run_until_stalled();
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// fn label() -> &'static str {
+///     if store::<Theme>().0.dark { "dark" } else { "light" }
+/// }
 ///
-/// sender.send(()).unwrap();
+/// let mut rt = RunLoop::new(|| (toggle(), label()));
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// let (theme, label) = rt.run_once();
+/// assert_eq!(label, "light");
+///
+/// theme.set(Theme { dark: true });
+/// assert_eq!(rt.run_once().1, "dark");
 /// ```
-#[topo::nested]
 #[illicit::from_env(rt: &Context)]
-pub fn load_once<Fut, Output>(init: impl FnOnce() -> Fut) -> Poll<Output>
+pub fn store<State>() -> (Commit<State>, Key<State>)
 where
-    Fut: Future<Output = Output> + 'static,
-    Output: Clone + 'static,
+    State: Default + 'static,
 {
-    rt.load_with(&CallId::current(), &(), |()| init(), Clone::clone)
+    rt.store()
 }
 
-/// Load a value from a future, cloning it on subsequent revisions after it is
-/// first returned. Re-initializes the loading future if the capture argument
-/// changes from previous revisions.
+/// Calls `child` with `value` available to [`consume`] anywhere within it,
+/// returning the result of `child`.
+///
+/// Values cached with [`cache`], [`once`], and related functions which
+/// consumed the provided value are re-initialized when `value` changes, as are
+/// [`derived`] values. Changes are detected with `PartialEq` against the value
+/// provided by the previous revision at this callsite.
 ///
 /// # Example
 ///
 /// ```
-/// use futures::{channel::oneshot, executor::LocalPool};
-/// use moxie::{load, runtime::RunLoop};
-/// use std::{
-///     sync::{
-///         atomic::{AtomicU64, Ordering},
-///         mpsc::channel,
-///     },
-///     task::Poll,
-/// };
+/// use moxie::{cache, consume, provide, runtime::RunLoop, state};
 ///
-/// let epoch = AtomicU64::new(0);
-/// let (send_futs, recv_futs) = channel();
+/// fn greeting() -> String {
+///     cache(&(), |()| format!("hello, {}", consume::<String>().unwrap()))
+/// }
 ///
 /// let mut rt = RunLoop::new(|| {
-///     // loads a new future when epoch changes
-///     load(&epoch.load(Ordering::Relaxed), |e| {
-///         let (sender, receiver) = oneshot::channel();
-///         send_futs.send((*e, sender)).unwrap();
-///         receiver
-///     })
+///     let (name, key) = state(|| String::from("world"));
+///     (provide(String::clone(&name), greeting), key)
 /// });
 ///
-/// let mut exec = LocalPool::new();
-/// rt.set_task_executor(exec.spawner());
+/// let (greeting, key) = rt.run_once();
+/// assert_eq!(greeting, "hello, world");
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
-/// exec.run_until_stalled();
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// key.set(String::from("moxie"));
+/// assert_eq!(rt.run_once().0, "hello, moxie", "the cached greeting re-ran");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn provide<T, Ret>(value: T, child: impl FnOnce() -> Ret) -> Ret
+where
+    T: PartialEq + 'static,
+{
+    rt.provide(&CallId::current(), value, child)
+}
+
+/// Returns the value of type `T` from the nearest enclosing call to
+/// [`provide`], if any. Reading the value within a cached closure causes the
+/// closure to re-run when the provided value changes.
+pub fn consume<T: 'static>() -> Option<Rc<T>> {
+    illicit::get::<runtime::Provided<T>>().ok().map(|provided| provided.read())
+}
+
+/// Calls `child`, returning the result of calling `fallback` with the [`Panic`]
+/// if `child` panics.
 ///
-/// // resolve the future
-/// let (created_in_epoch, sender) = recv_futs.recv().unwrap();
-/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
-/// assert_eq!(created_in_epoch, 0);
+/// Values cached in `child` after the point at which it panicked aren't dropped
+/// while it keeps panicking, so they're still available once it recovers.
+/// Values cached outside of the boundary are collected as usual.
 ///
-/// sender.send(()).unwrap();
+/// The panic is still reported to the panic hook, which by default prints it
+/// to stderr.
 ///
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// # Example
 ///
-/// // force the future to be reinitialized
-/// epoch.store(1, Ordering::Relaxed);
+/// ```
+/// use moxie::{catch_boundary, runtime::RunLoop, state};
 ///
-/// assert_eq!(rt.run_once(), Poll::Pending);
+/// let mut rt = RunLoop::new(|| {
+///     let (count, key) = state(|| 0);
+///     let label = catch_boundary(
+///         || {
+///             assert!(*count < 2, "count is too high");
+///             format!("count: {}", count)
+///         },
+///         |panic| format!("error: {}", panic.message().unwrap()),
+///     );
+///     (label, key)
+/// });
 ///
-/// // resolve the future
-/// let (created_in_epoch, sender) = recv_futs.recv().unwrap();
-/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
-/// assert_eq!(created_in_epoch, 1);
+/// let (label, key) = rt.run_once();
+/// assert_eq!(label, "count: 0");
 ///
-/// sender.send(()).unwrap();
+/// key.set(2);
+/// let (label, key) = rt.run_once();
+/// assert_eq!(label, "error: count is too high");
 ///
-/// exec.run();
-/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// key.set(1);
+/// assert_eq!(rt.run_once().0, "count: 1");
 /// ```
 #[topo::nested]
 #[illicit::from_env(rt: &Context)]
-pub fn load<Arg, Input, Fut, Output>(
-    capture: &Arg,
-    init: impl FnOnce(&Input) -> Fut,
-) -> Poll<Output>
+pub fn catch_boundary<Ret>(
+    child: impl FnOnce() -> Ret,
+    fallback: impl FnOnce(&Panic) -> Ret,
+) -> Ret {
+    rt.catch_boundary(&CallId::current(), child, fallback)
+}
+
+/// Runs `op`, making all of the commits it enqueues to state variables visible
+/// together. Commits made within `op` are staged until it returns, and the
+/// runtimes which own the state variables are woken once after `op` returns
+/// rather than after each commit. Updates within `op` observe the values staged
+/// by earlier updates.
+///
+/// If `op` panics, the commits it staged are discarded. Calls nested within
+/// another transaction join the outer transaction. Transactions can be run from
+/// event handlers outside of a revision or within a revision, in which case the
+/// staged commits are visible the next time the state variables are rooted
+/// after `op` returns.
+///
+/// # Example
+///
+/// ```
+/// use futures::task::waker;
+/// use moxie::{runtime::RunLoop, state, testing::BoolWaker, transaction};
+///
+/// let mut rt = RunLoop::new(|| (state(|| 1).1, state(|| 1).1));
+/// let track_wakes = BoolWaker::new();
+/// rt.set_state_change_waker(waker(track_wakes.clone()));
+/// let (numerator, denominator) = rt.run_once();
+///
+/// transaction(|| {
+///     numerator.update(|n| Some(n * 2));
+///     assert!(!track_wakes.is_woken(), "wakes are deferred until the transaction finishes");
+///     assert_eq!(*numerator.commit(), 1, "commits aren't visible until the transaction finishes");
+///     numerator.update(|n| Some(n * 2));
+///     denominator.set(2);
+/// });
+/// assert!(track_wakes.is_woken());
+///
+/// let (numerator, denominator) = rt.run_once();
+/// assert_eq!((*numerator, *denominator), (4, 2));
+/// ```
+pub fn transaction<R>(op: impl FnOnce() -> R) -> R {
+    runtime::Transaction::run(op)
+}
+
+/// Caches the return of `op` at this callsite, re-running it only when one of
+/// the state variables it read has received a new commit.
+///
+/// State variables are read by rooting them, either by calling [`state`] or
+/// [`cache_state`] within `op` or by calling [`Key::commit`]. Values captured
+/// by `op` in other ways are not compared between revisions, use [`cache`] for
+/// those.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{derived, runtime::RunLoop, state};
+/// use std::cell::Cell;
+///
+/// let num_runs = Cell::new(0);
+/// let mut rt = RunLoop::new(|| {
+///     let (_, first) = state(|| 1u64);
+///     let (_, second) = state(|| 2u64);
+///     let (_, unrelated) = state(|| 0u64);
+///
+///     let sum = derived(|| {
+///         num_runs.set(num_runs.get() + 1);
+///         *first.commit() + *second.commit()
+///     });
+///     (sum, first, unrelated)
+/// });
+///
+/// let (sum, first, unrelated) = rt.run_once();
+/// assert_eq!(sum, 3);
+/// assert_eq!(num_runs.get(), 1);
+///
+/// unrelated.set(10);
+/// let (sum, _, _) = rt.run_once();
+/// assert_eq!(sum, 3);
+/// assert_eq!(num_runs.get(), 1, "unrelated commits don't re-run the closure");
+///
+/// first.set(5);
+/// let (sum, _, _) = rt.run_once();
+/// assert_eq!(sum, 7);
+/// assert_eq!(num_runs.get(), 2, "re-runs after a commit to a state variable it read");
+///
+/// let (sum, _, _) = rt.run_once();
+/// assert_eq!(sum, 7);
+/// assert_eq!(num_runs.get(), 2);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn derived<Output>(op: impl FnOnce() -> Output) -> Output
 where
-    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
-    Input: Borrow<Arg> + 'static,
-    Fut: Future<Output = Output> + 'static,
     Output: Clone + 'static,
 {
-    rt.load_with(&CallId::current(), capture, init, Clone::clone)
+    rt.derived(&CallId::current(), op)
 }
 
-/// A read-only pointer to the value of a state variable *at a particular
-/// revision*.
+/// Runs `setup` after the current [`runtime::Revision`] if `deps` changed since
+/// the last revision in which this callsite was called. The function returned by
+/// `setup` is run to clean up the effect after the revision in which `deps` next
+/// changes or in which this callsite is no longer called.
 ///
-/// Reads through a commit are not guaranteed to be the latest value visible to
-/// the runtime. Commits should be shared and used within the context of a
-/// single [`crate::runtime::Revision`], being re-loaded from the state variable
-/// each time.
+/// Effects never run while the root function of a revision is executing, see
+/// [`runtime::Runtime`] for the order in which setups and cleanups are run.
 ///
-/// See [`state`] and [`cache_state`] for examples.
+/// # Example
+///
+/// ```
+/// use moxie::{effect, runtime::RunLoop};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let epoch = Rc::new(RefCell::new(Some(0)));
+///
+/// let (rt_log, rt_epoch) = (log.clone(), epoch.clone());
+/// let mut rt = RunLoop::new(move || {
+///     if let Some(current) = *rt_epoch.borrow() {
+///         let log = rt_log.clone();
+///         effect(&current, move |e| {
+///             log.borrow_mut().push(format!("setup {}", e));
+///             let e = *e;
+///             move || log.borrow_mut().push(format!("cleanup {}", e))
+///         });
+///     }
+///     rt_log.borrow_mut().push(String::from("root returned"));
+/// });
+///
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["root returned", "setup 0"]);
+///
+/// rt.run_once();
+/// assert_eq!(log.borrow().len(), 3, "deps didn't change, setup doesn't re-run");
+///
+/// log.borrow_mut().clear();
+/// epoch.replace(Some(1));
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["root returned", "cleanup 0", "setup 1"]);
+///
+/// log.borrow_mut().clear();
+/// epoch.replace(None);
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["root returned", "cleanup 1"], "callsite was not called");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn effect<Arg, Input, Cleanup>(deps: &Arg, setup: impl FnOnce(&Input) -> Cleanup + 'static)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Cleanup: FnOnce() + 'static,
+{
+    rt.effect(&CallId::current(), deps, setup)
+}
+
+/// Load a value from the future returned by `init` whenever `capture` changes,
+/// returning the result of calling `with` with the loaded value. Cancels the
+/// running future after any revision during which this call was not made.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{
+///     load_with,
+///     testing::{Resolver, TestLoop},
+/// };
+/// use std::{cell::Cell, rc::Rc, task::Poll};
+///
+/// let epoch = Rc::new(Cell::new(0));
+/// let resolver = Resolver::new();
+/// let (current, requested) = (epoch.clone(), resolver.clone());
+///
+/// let mut test = TestLoop::new(move || {
+///     // loads a new future when epoch changes
+///     load_with(&current.get(), |_| requested.future(), |n: &u32| n * 2)
+/// });
+///
+/// assert_eq!(test.run_once(), Poll::Pending);
+/// test.run_tasks();
+/// assert_eq!(test.run_once(), Poll::Pending);
+/// assert_eq!(resolver.requested(), 1, "only one future is created per epoch");
+///
+/// // resolve the future
+/// assert!(resolver.resolve(21));
+/// test.run_tasks();
+/// assert_eq!(test.run_once(), Poll::Ready(42));
+///
+/// // force the future to be reinitialized
+/// epoch.set(1);
+///
+/// assert_eq!(test.run_once(), Poll::Pending);
+/// test.run_tasks();
+/// assert_eq!(test.run_once(), Poll::Pending);
+/// assert_eq!(resolver.requested(), 2, "only one future is created per epoch");
+///
+/// // resolve the future
+/// assert!(resolver.resolve(4));
+/// test.run_tasks();
+/// assert_eq!(test.run_once(), Poll::Ready(8));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_with<Arg, Input, Fut, Output, Ret>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Output> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), Priority::Normal, arg, init, with)
+}
+
+/// Calls [`load_with`], spawning new futures onto the runtime's executor for
+/// `priority`. See [`runtime::Runtime::set_priority_executor`].
+///
+/// The priority is only used when a new future is spawned, changing it does
+/// not restart a running future.
+///
+/// # Example
+///
+/// ```
+/// use futures::{executor::LocalPool, future::ready};
+/// use moxie::{
+///     load_with_priority,
+///     runtime::{Priority, RunLoop},
+/// };
+/// use std::task::Poll;
+///
+/// let mut rt = RunLoop::new(|| {
+///     let prefetched = load_with_priority(Priority::Idle, &(), |()| ready(1), |n| *n);
+///     let clicked = load_with_priority(Priority::UserBlocking, &(), |()| ready(2), |n| *n);
+///     (prefetched, clicked)
+/// });
+///
+/// let mut normal = LocalPool::new();
+/// let mut idle = LocalPool::new();
+/// rt.set_task_executor(normal.spawner());
+/// rt.set_priority_executor(Priority::Idle, idle.spawner());
+///
+/// assert_eq!(rt.run_once(), (Poll::Pending, Poll::Pending));
+///
+/// // no executor was set for user-blocking tasks, so they're spawned as normal
+/// normal.run_until_stalled();
+/// assert_eq!(rt.run_once(), (Poll::Pending, Poll::Ready(2)));
+///
+/// idle.run_until_stalled();
+/// assert_eq!(rt.run_once(), (Poll::Ready(1), Poll::Ready(2)));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_with_priority<Arg, Input, Fut, Output, Ret>(
+    priority: Priority,
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Output> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), priority, arg, init, with)
+}
+
+/// Load a value from the future returned by `init` whenever `capture` changes,
+/// running it according to `policy`. Returns the result of calling `with` with
+/// the outcome of the last attempt. `init` may be called more than once per
+/// value of `capture` in order to retry failed attempts.
+///
+/// Timeouts and retry backoff require a clock to be provided with
+/// [`runtime::Runtime::set_clock`].
+///
+/// # Example
+///
+/// ```
+/// use futures::{executor::LocalPool, future::ready};
+/// use moxie::{
+///     load_with_policy,
+///     runtime::{LoadError, LoadPolicy, RunLoop},
+/// };
+/// use std::{cell::Cell, rc::Rc, task::Poll};
+///
+/// let page = Rc::new(Cell::new(1));
+/// let current_page = page.clone();
+/// let policy = LoadPolicy::new().stale_while_revalidate();
+/// let mut rt = RunLoop::new(move || {
+///     load_with_policy(
+///         &policy,
+///         &current_page.get(),
+///         |&page| ready(if page < 3 { Ok(page * 10) } else { Err("no such page") }),
+///         Clone::clone,
+///     )
+/// });
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(10)));
+///
+/// page.set(2);
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(10)), "previous value shown while loading");
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(20)));
+///
+/// page.set(3);
+/// rt.run_once();
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Ready(Err(LoadError::Failed("no such page"))));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_with_policy<Arg, Input, Fut, Output, Error, Ret>(
+    policy: &runtime::LoadPolicy,
+    capture: &Arg,
+    init: impl FnMut(&Input) -> Fut + 'static,
+    with: impl FnOnce(&Result<Output, runtime::LoadError<Error>>) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Clone + 'static,
+    Fut: Future<Output = Result<Output, Error>> + 'static,
+    Output: 'static,
+    Error: 'static,
+    Ret: 'static,
+{
+    rt.load_with_policy(&CallId::current(), policy, capture, init, with)
+}
+
+/// Spawn the stream returned by `init` whenever `capture` changes, returning
+/// the latest item it has produced or `initial` if it hasn't produced any yet.
+/// Each item is committed to a state variable, waking the runtime.
+///
+/// The stream is cancelled after any revision during which this call was not
+/// made, or when `capture` changes and a new stream is spawned in its place.
+///
+/// # Example
+///
+/// ```
+/// use futures::{channel::mpsc, executor::LocalPool};
+/// use moxie::{runtime::RunLoop, stream_latest};
+/// use std::cell::RefCell;
+///
+/// let (mut sender, receiver) = mpsc::unbounded();
+/// let receiver = RefCell::new(Some(receiver));
+/// let mut rt = RunLoop::new(|| stream_latest(&(), |()| receiver.take().unwrap(), 0));
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), 0);
+///
+/// sender.unbounded_send(1).unwrap();
+/// sender.unbounded_send(2).unwrap();
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), 2, "only the latest item is kept");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn stream_latest<Arg, Input, S, Item>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> S,
+    initial: Item,
+) -> Item
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    S: Stream<Item = Item> + 'static,
+    Item: Clone + 'static,
+{
+    rt.stream_latest(&CallId::current(), capture, init, initial)
+}
+
+/// Returns false until `duration` has passed since the first revision in which
+/// this callsite was called with the same `duration`, then returns true. Wakes
+/// the runtime once the duration has passed.
+///
+/// # Panics
+///
+/// If the runtime doesn't have a task executor and a [`runtime::Clock`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{testing::TestLoop, timeout};
+/// use std::time::Duration;
+///
+/// let mut test = TestLoop::new(|| timeout(Duration::from_millis(500)));
+/// assert!(!test.run_once());
+///
+/// test.advance(Duration::from_millis(499));
+/// test.assert_woken(0);
+/// assert!(!test.run_once());
+///
+/// test.advance(Duration::from_millis(1));
+/// test.assert_woken(1);
+/// assert!(test.run_once());
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn timeout(duration: Duration) -> bool {
+    rt.timeout(&CallId::current(), duration)
+}
+
+/// Returns the number of times `period` has passed since the first revision in
+/// which this callsite was called with the same `period`. Wakes the runtime
+/// each time the period passes. Each tick is due a whole number of periods
+/// after the first revision, even if the runtime's tasks run late.
+///
+/// # Panics
+///
+/// If `period` is zero, or if the runtime doesn't have a task executor and a
+/// [`runtime::Clock`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{interval, testing::TestLoop};
+/// use std::time::Duration;
+///
+/// let mut test = TestLoop::new(|| interval(Duration::from_millis(300)));
+/// assert_eq!(test.run_once(), 0);
+///
+/// test.advance(Duration::from_secs(1));
+/// test.assert_woken(3);
+/// assert_eq!(test.run_once(), 3);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn interval(period: Duration) -> u64 {
+    rt.interval(&CallId::current(), period)
+}
+
+/// Calls [`load_with`] but never re-initializes the loading future.
+///
+/// # Example
+///
+/// ```
+/// use futures::{channel::oneshot, executor::LocalPool};
+/// use moxie::{load_once_with, runtime::RunLoop};
+/// use std::task::Poll;
+///
+/// let (sender, receiver) = oneshot::channel();
+/// let mut receiver = Some(receiver);
+/// let mut rt = RunLoop::new(|| load_once_with(|| receiver.take().unwrap(), |res| res.clone()));
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Pending);
+///
+/// sender.send(()).unwrap();
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_once_with<Fut, Output, Ret>(
+    init: impl FnOnce() -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Fut: Future<Output = Output> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), Priority::Normal, &(), |()| init(), with)
+}
+
+/// Calls [`load_with`], never re-initializes the loading future, and clones the
+/// returned value on each revision once the future has completed and returned.
+///
+/// # Example
+///
+/// ```
+/// use futures::{channel::oneshot, executor::LocalPool};
+/// use moxie::{load_once, runtime::RunLoop};
+/// use std::task::Poll;
+///
+/// let (sender, receiver) = oneshot::channel();
+/// let mut receiver = Some(receiver);
+/// let mut rt = RunLoop::new(|| load_once(|| receiver.take().unwrap()));
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Pending);
+///
+/// sender.send(()).unwrap();
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_once<Fut, Output>(init: impl FnOnce() -> Fut) -> Poll<Output>
+where
+    Fut: Future<Output = Output> + 'static,
+    Output: Clone + 'static,
+{
+    rt.load_with(&CallId::current(), Priority::Normal, &(), |()| init(), Clone::clone)
+}
+
+/// Load a value from a future, cloning it on subsequent revisions after it is
+/// first returned. Re-initializes the loading future if the capture argument
+/// changes from previous revisions.
+///
+/// # Example
+///
+/// ```
+/// use futures::{channel::oneshot, executor::LocalPool};
+/// use moxie::{load, runtime::RunLoop};
+/// use std::{
+///     sync::{
+///         atomic::{AtomicU64, Ordering},
+///         mpsc::channel,
+///     },
+///     task::Poll,
+/// };
+///
+/// let epoch = AtomicU64::new(0);
+/// let (send_futs, recv_futs) = channel();
+///
+/// let mut rt = RunLoop::new(|| {
+///     // loads a new future when epoch changes
+///     load(&epoch.load(Ordering::Relaxed), |e| {
+///         let (sender, receiver) = oneshot::channel();
+///         send_futs.send((*e, sender)).unwrap();
+///         receiver
+///     })
+/// });
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Pending);
+///
+/// // resolve the future
+/// let (created_in_epoch, sender) = recv_futs.recv().unwrap();
+/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
+/// assert_eq!(created_in_epoch, 0);
+///
+/// sender.send(()).unwrap();
+///
+/// exec.run();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+///
+/// // force the future to be reinitialized
+/// epoch.store(1, Ordering::Relaxed);
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+///
+/// // resolve the future
+/// let (created_in_epoch, sender) = recv_futs.recv().unwrap();
+/// assert!(recv_futs.try_recv().is_err(), "only one channel is created per epoch");
+/// assert_eq!(created_in_epoch, 1);
+///
+/// sender.send(()).unwrap();
+///
+/// exec.run();
+/// assert_eq!(rt.run_once(), Poll::Ready(Ok(())));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load<Arg, Input, Fut, Output>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+) -> Poll<Output>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Output> + 'static,
+    Output: Clone + 'static,
+{
+    rt.load_with(&CallId::current(), Priority::Normal, capture, init, Clone::clone)
+}
+
+/// A read-only pointer to the value of a state variable *at a particular
+/// revision*.
+///
+/// Reads through a commit are not guaranteed to be the latest value visible to
+/// the runtime. Commits should be shared and used within the context of a
+/// single [`crate::runtime::Revision`], being re-loaded from the state variable
+/// each time.
+///
+/// See [`state`] and [`cache_state`] for examples.
 #[derive(Eq, Hash, PartialEq)]
 pub struct Commit<State> {
     id: CallId,
@@ -731,102 +1485,633 @@ impl<State> Key<State> {
     /// assert_eq!(first_key, second_key, "same state variable");
     /// ```
     pub fn update(&self, updater: impl FnOnce(&State) -> Option<State>) {
-        let mut var = self.var.lock();
+        let mut var = self.var.lock();
+        if let Some(new) = updater(var.latest()) {
+            var.enqueue_commit(new);
+        }
+    }
+
+    /// Set a new value for the state variable, immediately taking effect.
+    fn force(&self, new: State) {
+        self.var.lock().enqueue_commit(new);
+    }
+
+    /// Returns the latest commit to the state variable, performing any pending
+    /// commit first. When called within [`derived`], the closure will re-run
+    /// after the state variable next receives a commit.
+    ///
+    /// See [`derived`] for an example.
+    pub fn commit(&self) -> Commit<State>
+    where
+        State: 'static,
+    {
+        runtime::Var::root(self.var.clone()).0
+    }
+
+    /// Returns a stream of every commit the state variable receives after this
+    /// call, in the order they're made. Commits made within a [`transaction`]
+    /// are yielded once they become visible, or once the state variable is
+    /// garbage collected if that happens first. The stream ends once the state
+    /// variable has been garbage collected.
+    ///
+    /// Useful for observing state from outside of the runtime, for example to
+    /// save it as it changes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::{executor::block_on, stream::StreamExt};
+    /// use moxie::{runtime::RunLoop, state};
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let live = Rc::new(Cell::new(true));
+    /// let root_live = live.clone();
+    /// let mut rt = RunLoop::new(move || if root_live.get() { Some(state(|| 0).1) } else { None });
+    ///
+    /// let key = rt.run_once().unwrap();
+    /// let changes = key.changes();
+    /// key.set(1);
+    /// key.set(2);
+    ///
+    /// live.set(false);
+    /// rt.run_once();
+    ///
+    /// let changes: Vec<u32> = block_on(changes.map(|commit| *commit).collect());
+    /// assert_eq!(changes, [1, 2]);
+    /// ```
+    pub fn changes(&self) -> impl Stream<Item = Commit<State>> {
+        self.var.lock().changes()
+    }
+
+    /// Returns a [`KeyLens`] focused on the part of the state variable which
+    /// `get` and `get_mut` borrow, like a field of a struct or an element of a
+    /// collection. Updates through the lens commit a copy of the whole value
+    /// with the part replaced.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{runtime::RunLoop, state};
+    ///
+    /// #[derive(Clone, Debug, PartialEq)]
+    /// struct Todo {
+    ///     title: String,
+    ///     done: bool,
+    /// }
+    ///
+    /// let mut rt = RunLoop::new(|| {
+    ///     state(|| vec![Todo { title: String::from("write docs"), done: false }])
+    /// });
+    ///
+    /// let (_, todos) = rt.run_once();
+    /// let first = todos.lens(|todos| &todos[0], |todos| &mut todos[0]);
+    /// let title = first.lens(|todo| &todo.title, |todo| &mut todo.title);
+    /// assert_eq!(*title, "write docs");
+    ///
+    /// first.lens(|todo| &todo.done, |todo| &mut todo.done).set(true);
+    /// title.mutate(|title| title.push_str(" and tests"));
+    ///
+    /// let (todos, _) = rt.run_once();
+    /// assert_eq!(todos[0], Todo { title: String::from("write docs and tests"), done: true });
+    /// ```
+    pub fn lens<Part>(
+        &self,
+        get: impl Fn(&State) -> &Part + Send + Sync + 'static,
+        get_mut: impl Fn(&mut State) -> &mut Part + Send + Sync + 'static,
+    ) -> KeyLens<State, Part> {
+        self.try_lens(move |state| Some(get(state)), move |state| Some(get_mut(state)))
+    }
+
+    /// Returns a [`KeyLens`] focused on a part of the state variable which may
+    /// be missing, like an element of a collection which may be removed.
+    /// Updates through the lens do nothing while `get` or `get_mut` return
+    /// `None`. See [`Key::lens`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{runtime::RunLoop, state};
+    ///
+    /// let mut rt = RunLoop::new(|| state(|| vec![1, 2]));
+    ///
+    /// let (_, numbers) = rt.run_once();
+    /// let second = numbers.try_lens(|n| n.get(1), |n| n.get_mut(1));
+    /// second.set(3);
+    /// numbers.set(vec![1]);
+    ///
+    /// let (_, numbers) = rt.run_once();
+    /// let second = numbers.try_lens(|n| n.get(1), |n| n.get_mut(1));
+    /// assert_eq!(second.get(), None);
+    /// second.set(4);
+    /// assert_eq!(*rt.run_once().0, [1]);
+    /// ```
+    pub fn try_lens<Part>(
+        &self,
+        get: impl Fn(&State) -> Option<&Part> + Send + Sync + 'static,
+        get_mut: impl Fn(&mut State) -> Option<&mut Part> + Send + Sync + 'static,
+    ) -> KeyLens<State, Part> {
+        KeyLens { key: self.clone(), get: Arc::new(get), get_mut: Arc::new(get_mut) }
+    }
+
+    // TODO(#197) delete this and remove the Deref impl
+    fn refresh(&mut self)
+    where
+        State: 'static,
+    {
+        self.commit_at_root = runtime::Var::root(self.var.clone()).0;
+    }
+}
+
+impl<State> Key<State>
+where
+    State: PartialEq,
+{
+    /// Commits a new state value if it is unequal to the current value and the
+    /// state variable is still live. Has the same properties as
+    /// [update](Key::update) regarding waking the runtime.
+    ///
+    /// See [`state`] and [`cache_state`] for examples.
+    pub fn set(&self, new: State) {
+        self.update(|prev| if prev == &new { None } else { Some(new) });
+    }
+}
+
+impl<State> Key<State>
+where
+    State: Clone + PartialEq,
+{
+    /// Mutates a copy of the current state, committing the update if it results
+    /// in a change. Has the same properties as [update](Key::update)
+    /// See [`state`] and [`cache_state`] for examples.
+    pub fn mutate(&self, op: impl FnOnce(&mut State)) {
+        self.update(|prev| {
+            let mut new = prev.clone();
+            op(&mut new);
+            if prev == &new {
+                None
+            } else {
+                Some(new)
+            }
+        });
+    }
+}
+
+impl<State> Clone for Key<State> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, commit_at_root: self.commit_at_root.clone(), var: self.var.clone() }
+    }
+}
+
+impl<State> Deref for Key<State> {
+    type Target = State;
+
+    fn deref(&self) -> &Self::Target {
+        self.commit_at_root.deref()
+    }
+}
+
+impl<State> Debug for Key<State>
+where
+    State: Debug,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.commit_at_root.fmt(f)
+    }
+}
+
+impl<State> Display for Key<State>
+where
+    State: Display,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.commit_at_root.fmt(f)
+    }
+}
+
+impl<State> PartialEq for Key<State> {
+    /// Keys are considered equal if they point to the same state variable.
+    /// Importantly, they will compare as equal even if they contain
+    /// different snapshots of the state variable due to having been
+    /// initialized in different revisions.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.var, &other.var)
+    }
+}
+
+impl<State> Eq for Key<State> {}
+
+impl<State> Hash for Key<State> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.id.hash(hasher);
+    }
+}
+
+/// A `KeyLens` offers access to part of a state variable, created with
+/// [`Key::lens`] or [`Key::try_lens`]. Reads see the part of the snapshot taken
+/// when the parent [`Key`] was created, and writes commit to the parent state
+/// variable.
+///
+/// Like [`Key`]s, lenses are equal if they point to the same state variable,
+/// even if they were created in different revisions. They're compared without
+/// their projections, so lenses focused on different parts of the same state
+/// variable are equal too.
+pub struct KeyLens<State, Part> {
+    key: Key<State>,
+    get: LensGet<State, Part>,
+    get_mut: LensGetMut<State, Part>,
+}
+
+type LensGet<State, Part> = Arc<dyn Fn(&State) -> Option<&Part> + Send + Sync>;
+type LensGetMut<State, Part> = Arc<dyn Fn(&mut State) -> Option<&mut Part> + Send + Sync>;
+
+impl<State, Part> KeyLens<State, Part> {
+    /// Returns the `topo::CallId` at which the state variable is bound.
+    pub fn id(&self) -> CallId {
+        self.key.id
+    }
+
+    /// Returns the key to the whole state variable.
+    pub fn key(&self) -> &Key<State> {
+        &self.key
+    }
+
+    /// Returns the part of the snapshot taken when the parent [`Key`] was
+    /// created, if it's present.
+    pub fn get(&self) -> Option<&Part> {
+        (self.get)(&self.key)
+    }
+
+    /// Returns a lens focused on part of this lens' part. See [`Key::lens`].
+    pub fn lens<Inner>(
+        &self,
+        get: impl Fn(&Part) -> &Inner + Send + Sync + 'static,
+        get_mut: impl Fn(&mut Part) -> &mut Inner + Send + Sync + 'static,
+    ) -> KeyLens<State, Inner>
+    where
+        State: 'static,
+        Part: 'static,
+    {
+        self.try_lens(move |part| Some(get(part)), move |part| Some(get_mut(part)))
+    }
+
+    /// Returns a lens focused on part of this lens' part which may be missing.
+    /// See [`Key::try_lens`].
+    pub fn try_lens<Inner>(
+        &self,
+        get: impl Fn(&Part) -> Option<&Inner> + Send + Sync + 'static,
+        get_mut: impl Fn(&mut Part) -> Option<&mut Inner> + Send + Sync + 'static,
+    ) -> KeyLens<State, Inner>
+    where
+        State: 'static,
+        Part: 'static,
+    {
+        let (outer, outer_mut) = (self.get.clone(), self.get_mut.clone());
+        KeyLens {
+            key: self.key.clone(),
+            get: Arc::new(move |state| outer(state).and_then(&get)),
+            get_mut: Arc::new(move |state| outer_mut(state).and_then(&get_mut)),
+        }
+    }
+}
+
+impl<State, Part> KeyLens<State, Part>
+where
+    State: Clone,
+{
+    /// Runs `updater` with a reference to the part of the state variable's
+    /// latest value, and enqueues a commit of the whole value with the part
+    /// replaced if `updater` returns `Some`. Does nothing if the part is
+    /// missing. Has the same properties as [`Key::update`] regarding waking the
+    /// runtime.
+    pub fn update(&self, updater: impl FnOnce(&Part) -> Option<Part>) {
+        self.key.update(|prev| {
+            let part = updater((self.get)(prev)?)?;
+            let mut new = prev.clone();
+            *(self.get_mut)(&mut new)? = part;
+            Some(new)
+        });
+    }
+}
+
+impl<State, Part> KeyLens<State, Part>
+where
+    State: Clone,
+    Part: PartialEq,
+{
+    /// Commits a new value for the part if it is unequal to the current one.
+    /// See [`Key::set`].
+    pub fn set(&self, new: Part) {
+        self.update(|prev| if prev == &new { None } else { Some(new) });
+    }
+}
+
+impl<State, Part> KeyLens<State, Part>
+where
+    State: Clone,
+    Part: Clone + PartialEq,
+{
+    /// Mutates a copy of the current part, committing the update if it results
+    /// in a change. See [`Key::mutate`].
+    pub fn mutate(&self, op: impl FnOnce(&mut Part)) {
+        self.update(|prev| {
+            let mut new = prev.clone();
+            op(&mut new);
+            if prev == &new {
+                None
+            } else {
+                Some(new)
+            }
+        });
+    }
+}
+
+impl<State, Part> Clone for KeyLens<State, Part> {
+    fn clone(&self) -> Self {
+        Self { key: self.key.clone(), get: self.get.clone(), get_mut: self.get_mut.clone() }
+    }
+}
+
+impl<State, Part> Deref for KeyLens<State, Part> {
+    type Target = Part;
+
+    /// Returns the part of the snapshot taken when the parent [`Key`] was
+    /// created.
+    ///
+    /// # Panics
+    ///
+    /// If the part is missing. See [`KeyLens::get`].
+    fn deref(&self) -> &Self::Target {
+        self.get().expect("the lens' part to be present")
+    }
+}
+
+impl<State, Part> Debug for KeyLens<State, Part>
+where
+    Part: Debug,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.get().fmt(f)
+    }
+}
+
+impl<State, Part> Display for KeyLens<State, Part>
+where
+    Part: Display,
+{
+    /// Writes the part, or nothing if it's missing.
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.get() {
+            Some(part) => part.fmt(f),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<State, Part> PartialEq for KeyLens<State, Part> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<State, Part> Eq for KeyLens<State, Part> {}
+
+impl<State, Part> Hash for KeyLens<State, Part> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.key.hash(hasher);
+    }
+}
+
+/// A panic caught by [`catch_boundary`].
+pub struct Panic {
+    payload: Box<dyn Any + Send>,
+}
+
+impl Panic {
+    pub(crate) fn new(payload: Box<dyn Any + Send>) -> Self {
+        Self { payload }
+    }
+
+    /// Returns the panic's message if it was created from a string, as with
+    /// `panic!` and the assertion macros.
+    pub fn message(&self) -> Option<&str> {
+        if let Some(message) = self.payload.downcast_ref::<&'static str>() {
+            Some(message)
+        } else {
+            self.payload.downcast_ref::<String>().map(String::as_str)
+        }
+    }
+
+    /// Returns the value the panic was created with, for example to resume
+    /// unwinding with [`std::panic::resume_unwind`].
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.payload
+    }
+}
+
+impl Debug for Panic {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Panic").field("message", &self.message()).finish()
+    }
+}
+
+/// A `HistoryKey` offers access to a state variable declared with
+/// [`history_state`], recording each commit so that it can be undone.
+pub struct HistoryKey<State> {
+    key: Key<State>,
+    undo: Arc<Mutex<runtime::UndoStack<State>>>,
+}
+
+impl<State> HistoryKey<State> {
+    /// Returns the `topo::CallId` at which the state variable is bound.
+    pub fn id(&self) -> CallId {
+        self.key.id
+    }
+
+    /// Runs `updater` with a reference to the state variable's latest value,
+    /// and enqueues an undoable commit to the variable if `updater` returns
+    /// `Some`. Clears any commits which could have been redone. Has the same
+    /// properties as [`Key::update`] regarding waking the runtime.
+    pub fn update(&self, updater: impl FnOnce(&State) -> Option<State>) {
+        // always lock the history before the variable, as undo and redo do
+        let mut undo = self.undo.lock();
+        let mut var = self.key.var.lock();
         if let Some(new) = updater(var.latest()) {
-            var.enqueue_commit(new);
+            undo.commit(&mut var, new);
         }
     }
 
-    /// Set a new value for the state variable, immediately taking effect.
-    fn force(&self, new: State) {
-        self.var.lock().enqueue_commit(new);
+    /// Restores the value from before the most recent commit made with
+    /// [`HistoryKey::update`] which hasn't been undone. Returns false if there
+    /// were no commits to undo.
+    pub fn undo(&self) -> bool {
+        self.undo.lock().undo(&mut self.key.var.lock())
     }
 
-    // TODO(#197) delete this and remove the Deref impl
-    fn refresh(&mut self) {
-        self.commit_at_root = runtime::Var::root(self.var.clone()).0;
+    /// Restores the value from before the most recent undo. Returns false if
+    /// there were no undone commits to restore.
+    pub fn redo(&self) -> bool {
+        self.undo.lock().redo(&mut self.key.var.lock())
+    }
+
+    /// Returns true if there are commits which can be undone.
+    pub fn can_undo(&self) -> bool {
+        self.undo.lock().can_undo()
+    }
+
+    /// Returns true if there are undone commits which can be redone.
+    pub fn can_redo(&self) -> bool {
+        self.undo.lock().can_redo()
     }
 }
 
-impl<State> Key<State>
+impl<State> HistoryKey<State>
 where
     State: PartialEq,
 {
-    /// Commits a new state value if it is unequal to the current value and the
-    /// state variable is still live. Has the same properties as
-    /// [update](Key::update) regarding waking the runtime.
-    ///
-    /// See [`state`] and [`cache_state`] for examples.
+    /// Commits a new state value if it is unequal to the current value. Has
+    /// the same properties as [update](HistoryKey::update).
     pub fn set(&self, new: State) {
         self.update(|prev| if prev == &new { None } else { Some(new) });
     }
 }
 
-impl<State> Key<State>
+impl<State> Clone for HistoryKey<State> {
+    fn clone(&self) -> Self {
+        Self { key: self.key.clone(), undo: self.undo.clone() }
+    }
+}
+
+impl<State> Debug for HistoryKey<State>
 where
-    State: Clone + PartialEq,
+    State: Debug,
 {
-    /// Mutates a copy of the current state, committing the update if it results
-    /// in a change. Has the same properties as [update](Key::update)
-    /// See [`state`] and [`cache_state`] for examples.
-    pub fn mutate(&self, op: impl FnOnce(&mut State)) {
-        self.update(|prev| {
-            let mut new = prev.clone();
-            op(&mut new);
-            if prev == &new {
-                None
-            } else {
-                Some(new)
-            }
-        });
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.key.fmt(f)
     }
 }
 
-impl<State> Clone for Key<State> {
-    fn clone(&self) -> Self {
-        Self { id: self.id, commit_at_root: self.commit_at_root.clone(), var: self.var.clone() }
+impl<State> PartialEq for HistoryKey<State> {
+    /// History keys are considered equal if they point to the same state
+    /// variable.
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
     }
 }
 
-impl<State> Deref for Key<State> {
-    type Target = State;
+impl<State> Eq for HistoryKey<State> {}
 
-    fn deref(&self) -> &Self::Target {
-        self.commit_at_root.deref()
+impl<State> Hash for HistoryKey<State> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.key.hash(hasher);
     }
 }
 
-impl<State> Debug for Key<State>
-where
-    State: Debug,
-{
+/// A `Dispatch` sends actions to a state variable declared with
+/// [`reducer_state`].
+pub struct Dispatch<Action> {
+    id: CallId,
+    dispatch: Rc<dyn Fn(Action)>,
+}
+
+impl<Action> Dispatch<Action> {
+    /// Returns the `topo::CallId` at which the state variable is bound.
+    pub fn id(&self) -> CallId {
+        self.id
+    }
+
+    /// Runs the state variable's reducer with `action` and its latest value,
+    /// enqueuing a commit if the reducer returns a new value. Has the same
+    /// properties as [`Key::update`] regarding waking the runtime.
+    pub fn dispatch(&self, action: Action) {
+        (self.dispatch)(action);
+    }
+}
+
+impl<Action> Clone for Dispatch<Action> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, dispatch: self.dispatch.clone() }
+    }
+}
+
+impl<Action> Debug for Dispatch<Action> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        self.commit_at_root.fmt(f)
+        f.debug_tuple("Dispatch").field(&self.id).finish()
     }
 }
 
-impl<State> Display for Key<State>
-where
-    State: Display,
-{
+impl<Action> PartialEq for Dispatch<Action> {
+    /// Dispatches are considered equal if they send actions to the state
+    /// variable at the same callsite.
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<Action> Eq for Dispatch<Action> {}
+
+impl<Action> Hash for Dispatch<Action> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.id.hash(hasher);
+    }
+}
+
+/// A `Sender` commits values to a state variable declared with
+/// [`channel_state`], and can be sent to other threads.
+pub struct Sender<State> {
+    id: CallId,
+    var: Weak<Mutex<Var<State>>>,
+}
+
+impl<State> Sender<State> {
+    /// Returns the `topo::CallId` at which the state variable is bound.
+    pub fn id(&self) -> CallId {
+        self.id
+    }
+
+    /// Enqueues a commit of `value` to the state variable. Has the same
+    /// properties as [`Key::update`] regarding waking the runtime.
+    ///
+    /// Returns `value` as an error if the state variable has been garbage
+    /// collected.
+    pub fn send(&self, value: State) -> Result<(), State> {
+        match self.var.upgrade() {
+            Some(var) => {
+                var.lock().enqueue_commit(value);
+                Ok(())
+            }
+            None => Err(value),
+        }
+    }
+
+    /// Returns true if the state variable has been garbage collected, after
+    /// which every call to [`Sender::send`] fails.
+    pub fn is_closed(&self) -> bool {
+        self.var.strong_count() == 0
+    }
+}
+
+impl<State> Clone for Sender<State> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, var: self.var.clone() }
+    }
+}
+
+impl<State> Debug for Sender<State> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        self.commit_at_root.fmt(f)
+        f.debug_tuple("Sender").field(&self.id).finish()
     }
 }
 
-impl<State> PartialEq for Key<State> {
-    /// Keys are considered equal if they point to the same state variable.
-    /// Importantly, they will compare as equal even if they contain
-    /// different snapshots of the state variable due to having been
-    /// initialized in different revisions.
+impl<State> PartialEq for Sender<State> {
+    /// Senders are considered equal if they commit to the same state variable.
     fn eq(&self, other: &Self) -> bool {
-        Arc::ptr_eq(&self.var, &other.var)
+        self.var.ptr_eq(&other.var)
     }
 }
 
-impl<State> Eq for Key<State> {}
+impl<State> Eq for Sender<State> {}
 
-impl<State> Hash for Key<State> {
+impl<State> Hash for Sender<State> {
     fn hash<H: Hasher>(&self, hasher: &mut H) {
         self.id.hash(hasher);
     }
@@ -835,7 +2120,10 @@ impl<State> Hash for Key<State> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::runtime::{Revision, RunLoop};
+    use crate::{
+        runtime::{Revision, RunLoop, Runtime},
+        testing::TestLoop,
+    };
     use std::{cell::Cell, collections::HashSet, rc::Rc};
 
     fn with_test_logs(test: impl FnOnce()) {
@@ -850,6 +2138,175 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "tracing-spans")]
+    #[test]
+    fn revisions_and_calls_are_traced() {
+        use std::{fmt::Debug, sync::Arc};
+        use tracing::{
+            field::{Field, Visit},
+            span::{Attributes, Id},
+            Event, Subscriber,
+        };
+        use tracing_subscriber::{
+            layer::{Context, Layer},
+            prelude::*,
+            registry::LookupSpan,
+        };
+
+        /// Records the names of spans and the messages of events with their
+        /// parent span's name.
+        #[derive(Clone, Default)]
+        struct Traces(Arc<parking_lot::Mutex<Vec<String>>>);
+
+        impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Traces {
+            fn new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+                self.0.lock().push(attrs.metadata().name().to_string());
+            }
+
+            fn on_event(&self, event: &Event<'_>, cx: Context<'_, S>) {
+                struct Message(String);
+                impl Visit for Message {
+                    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                        if field.name() == "message" {
+                            self.0 = format!("{:?}", value);
+                        }
+                    }
+                }
+
+                let mut message = Message(String::new());
+                event.record(&mut message);
+                let parent = cx.current_span().metadata().map(|m| m.name()).unwrap_or_default();
+                self.0.lock().push(format!("{} in {}", message.0, parent));
+            }
+        }
+
+        let traces = Traces::default();
+        let subscriber = tracing_subscriber::registry().with(traces.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let mut rt = RunLoop::new(|| {
+                let (count, key) = state(|| 0);
+                if *count == 0 {
+                    key.set(1);
+                }
+            });
+            rt.run_once();
+        });
+
+        let traces = traces.0.lock();
+        assert_eq!(traces[0], "run_once");
+        for expected in &["call", "cache miss in call", "commit in call"] {
+            assert!(traces.iter().any(|t| t == expected), "{} missing from {:?}", expected, traces);
+        }
+    }
+
+    #[test]
+    fn keyed_state_follows_reordering() {
+        let mut rt = Runtime::new();
+        let counts = |keys: &[u8]| {
+            keyed(
+                keys.iter().copied(),
+                |k| *k,
+                |k| {
+                    let (count, key) = state(|| 0);
+                    key.set(*count + 1);
+                    (k, *count)
+                },
+            )
+        };
+
+        assert_eq!(rt.run_once(|| counts(&[1, 2])), [(1, 0), (2, 0)]);
+        assert_eq!(rt.run_once(|| counts(&[0, 2, 1])), [(0, 0), (2, 1), (1, 1)]);
+        assert_eq!(rt.run_once(|| counts(&[1])), [(1, 2)]);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn keyed_tells_duplicate_keys_apart() {
+        let mut rt = Runtime::new();
+        let keys = |items: Vec<u8>| keyed(items, |k| *k, |k| (k, state(|| k).1));
+
+        let first = rt.run_once(|| keys(vec![1, 2, 1]));
+        assert_ne!(first[0].1, first[2].1, "duplicates don't share state");
+
+        let second = rt.run_once(|| keys(vec![1, 1, 2]));
+        assert_eq!(first[0].1, second[0].1);
+        assert_eq!(first[2].1, second[1].1, "duplicates keep their order");
+    }
+
+    #[test]
+    fn interval_ticks_dont_drift() {
+        let mut test = TestLoop::new(|| interval(Duration::from_millis(300)));
+        assert_eq!(test.run_once(), 0);
+
+        // fire the first timer late, without running the interval's task in the meantime
+        test.clock().advance(Duration::from_millis(450));
+        test.run_tasks();
+        assert_eq!(test.run_once(), 1);
+
+        test.advance(Duration::from_millis(150));
+        assert_eq!(test.run_once(), 2, "second tick is due 600ms after the first revision");
+    }
+
+    #[test]
+    #[should_panic(expected = "non-zero period")]
+    fn interval_rejects_zero_period() {
+        TestLoop::new(|| interval(Duration::from_secs(0))).run_once();
+    }
+
+    #[test]
+    fn failed_subtrees_keep_their_cached_values() {
+        let created = Cell::new(0);
+        let mut rt = RunLoop::new(|| {
+            let (fail, set_fail) = state(|| false);
+            let ret = catch_boundary(
+                || {
+                    assert!(!*fail, "failing");
+                    once(|| created.set(created.get() + 1));
+                    true
+                },
+                |_| false,
+            );
+            (ret, set_fail)
+        });
+
+        let (ok, fail) = rt.run_once();
+        assert!(ok);
+        fail.set(true);
+        let (ok, fail) = rt.run_once();
+        assert!(!ok);
+        fail.set(false);
+
+        assert!(rt.run_once().0);
+        assert_eq!(created.get(), 1, "value created before the panic was retained");
+    }
+
+    #[test]
+    fn runtime_recovers_from_panicking_revisions() {
+        let created = Cell::new(0);
+        let root = |panic_after_b: bool, use_b: bool| {
+            let a = once(|| "a");
+            if use_b {
+                once(|| created.set(created.get() + 1));
+            }
+            assert!(!panic_after_b, "panicking mid-tree");
+            a
+        };
+        let mut rt = Runtime::new();
+
+        assert_eq!(rt.run_once(|| root(false, true)), "a");
+        assert_eq!(created.get(), 1);
+
+        let failed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            rt.run_once(|| root(true, true))
+        }));
+        assert!(failed.is_err());
+        assert_eq!(rt.revision(), Revision(2));
+
+        assert_eq!(rt.run_once(|| root(false, false)), "a", "renders after the failed revision");
+        assert_eq!(rt.run_once(|| root(false, true)), "a");
+        assert_eq!(created.get(), 2, "b's use by the failed revision was discarded");
+    }
+
     #[test]
     fn basic_cache() {
         with_test_logs(|| {
@@ -967,6 +2424,215 @@ mod tests {
         })
     }
 
+    #[test]
+    fn nested_derived_invalidates_outer() {
+        let (outer_runs, inner_runs) = (Cell::new(0), Cell::new(0));
+        let mut rt = RunLoop::new(|| {
+            let (_, key) = state(|| 1u8);
+            let doubled = derived(|| {
+                outer_runs.set(outer_runs.get() + 1);
+                derived(|| {
+                    inner_runs.set(inner_runs.get() + 1);
+                    *key.commit()
+                }) * 2
+            });
+            (doubled, key)
+        });
+
+        let (doubled, key) = rt.run_once();
+        assert_eq!(doubled, 2);
+        assert_eq!((outer_runs.get(), inner_runs.get()), (1, 1));
+
+        let (doubled, _) = rt.run_once();
+        assert_eq!(doubled, 2);
+        assert_eq!((outer_runs.get(), inner_runs.get()), (1, 1), "nothing changed");
+
+        key.set(2);
+        let (doubled, _) = rt.run_once();
+        assert_eq!(doubled, 4, "outer value must see the inner read");
+        assert_eq!((outer_runs.get(), inner_runs.get()), (2, 2));
+    }
+
+    #[test]
+    fn stores_outlive_revisions_which_dont_read_them() {
+        let derived_runs = Cell::new(0);
+        let read = Cell::new(true);
+        let mut rt = RunLoop::new(|| {
+            if read.get() {
+                let (count, key) = derived(|| {
+                    derived_runs.set(derived_runs.get() + 1);
+                    store::<u32>()
+                });
+                Some((*count, key))
+            } else {
+                None
+            }
+        });
+
+        let (count, key) = rt.run_once().unwrap();
+        assert_eq!(count, 0);
+        key.set(5);
+
+        read.set(false);
+        assert!(rt.run_once().is_none());
+        rt.run_once();
+
+        read.set(true);
+        assert_eq!(rt.run_once().unwrap().0, 5, "the store wasn't collected");
+        assert_eq!(derived_runs.get(), 2);
+
+        key.set(6);
+        assert_eq!(rt.run_once().unwrap().0, 6, "the store's read was tracked");
+        assert_eq!(derived_runs.get(), 3);
+    }
+
+    #[test]
+    fn consumers_rerun_when_provided_values_change() {
+        let (outer_runs, inner_runs) = (Cell::new(0), Cell::new(0));
+        let mut rt = RunLoop::new(|| {
+            let (provided, key) = state(|| 1u8);
+            let doubled = provide(*provided, || {
+                cache(&(), |()| {
+                    outer_runs.set(outer_runs.get() + 1);
+                    once(|| {
+                        inner_runs.set(inner_runs.get() + 1);
+                        *consume::<u8>().unwrap()
+                    }) * 2
+                })
+            });
+            (doubled, key)
+        });
+
+        let (doubled, key) = rt.run_once();
+        assert_eq!(doubled, 2);
+        assert_eq!((outer_runs.get(), inner_runs.get()), (1, 1));
+
+        assert_eq!(rt.run_once().0, 2);
+        assert_eq!((outer_runs.get(), inner_runs.get()), (1, 1), "provided an equal value");
+
+        key.set(3);
+        assert_eq!(rt.run_once().0, 6, "outer value must see the inner read");
+        assert_eq!((outer_runs.get(), inner_runs.get()), (2, 2));
+    }
+
+    #[test]
+    fn panicking_transaction_discards_commits() {
+        let mut rt = RunLoop::new(|| state(|| 0u8).1);
+        let count = rt.run_once();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            transaction(|| {
+                count.set(1);
+                panic!("bail out");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*count.commit(), 0, "aborted commit discarded");
+
+        count.update(|prev| Some(prev + 2));
+        assert_eq!(*rt.run_once(), 2, "later commits unaffected");
+    }
+
+    #[test]
+    fn lens_updates_commit_once_to_the_parent() {
+        use futures::{executor::block_on, stream::StreamExt};
+
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let mut rt = RunLoop::new(|| state(|| (1u8, vec![2u8])).1);
+        let pair = rt.run_once();
+        let changes = pair.changes();
+
+        let first = pair.lens(|(n, _)| n, |(n, _)| n);
+        let second = pair.try_lens(|(_, v)| v.first(), |(_, v)| v.first_mut());
+        let missing = pair.try_lens(|(_, v)| v.get(5), |(_, v)| v.get_mut(5));
+        assert_send_sync(&second);
+        let next = rt.run_once().try_lens(|(_, v)| v.first(), |(_, v)| v.first_mut());
+        assert_eq!(second, next, "lenses from later revisions are equal");
+
+        second.set(3);
+        first.set(1);
+        missing.set(4);
+        assert_eq!(missing.get(), None);
+        assert_eq!(rt.stats().commits(), 1, "unchanged and missing parts don't commit");
+
+        drop(rt);
+        let changes: Vec<(u8, Vec<u8>)> = block_on(changes.map(|c| (*c).clone()).collect());
+        assert_eq!(changes, [(1, vec![3])], "the whole value was committed once");
+    }
+
+    #[test]
+    fn aborted_transactions_keep_earlier_finished_commits() {
+        let mut rt = RunLoop::new(|| state(|| 0u8).1);
+        let count = rt.run_once();
+
+        transaction(|| count.set(1));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            transaction(|| {
+                count.set(2);
+                panic!("bail out");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(*rt.run_once(), 1, "finished commit survived the aborted one");
+    }
+
+    #[test]
+    fn aborted_transactions_leave_no_trace() {
+        let mut rt = RunLoop::new(|| state(|| 0u8).1);
+        rt.record_commits::<u8>();
+        let count = rt.run_once();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            transaction(|| {
+                count.set(1);
+                panic!("bail out");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(rt.commit_log().is_empty());
+        assert!(rt.committing().is_empty());
+        assert_eq!(rt.stats().commits(), 0);
+
+        count.set(2);
+        rt.run_once();
+        rt.travel_to(Revision(2));
+        assert_eq!(*rt.run_once(), 2, "only the visible commit was recorded");
+    }
+
+    #[test]
+    fn changes_only_include_finished_transactions() {
+        use futures::{executor::block_on, stream::StreamExt};
+
+        let live = Rc::new(Cell::new(true));
+        let root_live = live.clone();
+        let mut rt =
+            RunLoop::new(move || if root_live.get() { Some(state(|| 0u8).1) } else { None });
+        let count = rt.run_once().unwrap();
+        let changes = count.changes();
+
+        transaction(|| {
+            count.set(1);
+            count.set(2);
+        });
+        rt.run_once();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            transaction(|| {
+                count.set(3);
+                panic!("bail out");
+            })
+        }));
+        count.set(4);
+
+        // collected before the last transaction's commit became visible
+        transaction(|| count.set(5));
+        live.set(false);
+        rt.run_once();
+
+        let changes: Vec<u8> = block_on(changes.map(|commit| *commit).collect());
+        assert_eq!(changes, [2, 4, 5]);
+    }
+
     #[test]
     fn basic_loading_phases() {
         let mut pool = futures::executor::LocalPool::new();
@@ -1044,4 +2710,45 @@ mod tests {
             "must be no task holding the channel and able to receive a message"
         );
     }
+
+    #[test]
+    fn streams_restart_and_cancel() {
+        use futures::{channel::mpsc, executor::LocalPool};
+
+        let channels = std::cell::RefCell::new(Vec::new());
+        let epoch = Cell::new(Some(0));
+        let mut rt = RunLoop::new(|| {
+            epoch.get().map(|epoch| {
+                stream_latest(
+                    &epoch,
+                    |_| {
+                        let (send, recv) = mpsc::unbounded();
+                        channels.borrow_mut().push(send);
+                        recv
+                    },
+                    0,
+                )
+            })
+        });
+        let mut pool = LocalPool::new();
+        rt.set_task_executor(pool.spawner());
+
+        assert_eq!(rt.run_once(), Some(0));
+        channels.borrow()[0].unbounded_send(1).unwrap();
+        pool.run_until_stalled();
+        assert_eq!(rt.run_once(), Some(1));
+
+        epoch.set(Some(1));
+        assert_eq!(rt.run_once(), Some(0), "new stream starts from the initial value");
+        pool.run_until_stalled();
+        assert!(channels.borrow()[0].is_closed(), "old stream cancelled");
+        channels.borrow()[1].unbounded_send(2).unwrap();
+        pool.run_until_stalled();
+        assert_eq!(rt.run_once(), Some(2));
+
+        epoch.set(None);
+        assert_eq!(rt.run_once(), None);
+        pool.run_until_stalled();
+        assert!(channels.borrow()[1].is_closed(), "interest dropped, stream cancelled");
+    }
 }
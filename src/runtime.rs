@@ -1,14 +1,32 @@
 //! [`Runtime`]s are the primary integration point between moxie and
 //! embedding environments.
 
+mod boundary;
+mod clock;
 mod context;
+mod effects;
+mod pending;
+#[cfg(feature = "serde")]
+mod persist;
+mod policy;
+mod provided;
+mod reads;
+mod recorder;
+mod revisions;
+mod roots;
 mod runloop;
+mod send;
+mod stats;
+mod stores;
+mod transaction;
+mod undo;
 mod var;
 
 use dyn_cache::local::SharedLocalCache;
+pub use dyn_cache::NamespaceStats;
 use futures::{
-    future::LocalFutureObj,
-    task::{noop_waker, LocalSpawn, SpawnError},
+    future::{FutureObj, LocalFutureObj},
+    task::{LocalSpawn, Spawn, SpawnError},
 };
 use illicit::AsContext;
 use std::{
@@ -17,9 +35,32 @@ use std::{
     task::Waker,
 };
 
+pub use clock::Clock;
+use clock::RuntimeClock;
 pub(crate) use context::Context;
-pub use runloop::RunLoop;
-pub(crate) use var::Var;
+pub(crate) use effects::Effects;
+use pending::PendingCommits;
+pub use pending::Unstable;
+#[cfg(feature = "serde")]
+pub(crate) use persist::Persistence;
+#[cfg(feature = "serde")]
+pub use persist::StateSnapshot;
+pub use policy::{LoadError, LoadPolicy};
+pub(crate) use provided::Provided;
+pub(crate) use reads::Reads;
+pub use recorder::CommitRecord;
+pub(crate) use recorder::Recorder;
+use revisions::Revisions;
+use roots::Roots;
+pub use runloop::{RunLoop, SendRunLoop};
+pub(crate) use send::SendContext;
+pub use send::SendRuntime;
+pub use stats::RevisionStats;
+use stats::StatsCounters;
+use stores::{StoreSlot, Stores};
+pub(crate) use transaction::Transaction;
+pub(crate) use undo::UndoStack;
+pub(crate) use var::{CachedVar, Var};
 
 /// Revisions measure moxie's notion of time passing. Each `Runtime` increments
 /// its Revision on every iteration. `crate::Commit`s to state variables are
@@ -33,6 +74,8 @@ impl Revision {
     pub fn current() -> Self {
         if let Ok(r) = illicit::get::<Context>() {
             r.revision()
+        } else if let Ok(r) = illicit::get::<SendContext>() {
+            r.revision()
         } else {
             Revision::default()
         }
@@ -106,6 +149,20 @@ impl std::fmt::Debug for Revision {
 /// Each runtime expects to be able to spawn futures as async tasks, provided
 /// with [`Runtime::set_task_executor`]. By default a no-op spawner is provided.
 ///
+/// Tasks spawned with a [`Priority`] other than [`Priority::Normal`] can be
+/// sent to a separate executor with [`Runtime::set_priority_executor`].
+/// Priorities without their own executor fall back to the normal executor.
+///
+/// ## Effects
+///
+/// Side effects declared with [`crate::effect`] never run while the root
+/// function is executing. At the end of each call to [`Runtime::run_once`],
+/// after the root function has returned and the cache has been
+/// garbage-collected, the runtime first runs the cleanups of effects whose
+/// dependencies changed or whose callsites were dropped, in the order they were
+/// invalidated. It then runs the setups of new effects in the order their
+/// callsites were reached.
+///
 /// # Minimal Example
 ///
 /// This example has no side effects in its root function, and doesn't have any
@@ -123,10 +180,15 @@ impl std::fmt::Debug for Revision {
 ///
 /// [dyn-cache]: https://docs.rs/dyn-cache
 pub struct Runtime {
-    revision: Revision,
-    cache: SharedLocalCache,
+    revisions: Revisions<SharedLocalCache>,
+    effects: Effects,
+    #[cfg(feature = "serde")]
+    persistence: Persistence,
+    recorder: Recorder,
     spawner: Spawner,
-    wk: Waker,
+    clock: RuntimeClock,
+    roots: Roots,
+    stores: Stores,
 }
 
 impl Default for Runtime {
@@ -140,54 +202,372 @@ impl Runtime {
     /// task executor.
     pub fn new() -> Self {
         Self {
-            spawner: Spawner(Rc::new(JunkSpawner)),
-            revision: Revision(0),
-            cache: SharedLocalCache::default(),
-            wk: noop_waker(),
+            revisions: Revisions::default(),
+            spawner: Spawner::new(),
+            effects: Effects::default(),
+            #[cfg(feature = "serde")]
+            persistence: Persistence::default(),
+            recorder: Recorder::default(),
+            clock: RuntimeClock::default(),
+            roots: Roots::default(),
+            stores: Stores::default(),
         }
     }
 
     /// The current revision of the runtime, or how many times `run_once` has
     /// been invoked.
     pub fn revision(&self) -> Revision {
-        self.revision
+        self.revisions.revision
     }
 
     /// Runs the root closure once with access to the runtime context,
-    /// increments the runtime's `Revision`, and drops any cached values
-    /// which were not marked alive.
+    /// increments the runtime's `Revision`, drops any cached values
+    /// which were not marked alive, and runs any pending effects.
+    ///
+    /// # Panics
+    ///
+    /// If the root closure panics the panic is propagated to the caller after
+    /// discarding the marks which the revision made on cached values, so that
+    /// the next revision collects them as though the failed revision hadn't
+    /// used them. The runtime remains usable and the next call to `run_once`
+    /// starts a new revision. Effects scheduled by the failed revision are run
+    /// after the next one.
+    ///
+    /// With the `tracing-spans` feature enabled, each revision runs within a
+    /// `DEBUG`-level span named `run_once` which records the revision number.
     pub fn run_once<Out>(&mut self, op: impl FnOnce() -> Out) -> Out {
-        self.revision.0 += 1;
+        self.run_revision(&|_| false, op)
+    }
+
+    /// Runs a revision in which the named roots selected by `should_run` are
+    /// run before `op`, and the cached values of the others are kept alive.
+    fn run_revision<Out>(
+        &mut self,
+        should_run: &dyn Fn(&str) -> bool,
+        op: impl FnOnce() -> Out,
+    ) -> Out {
+        let revision = self.revisions.begin();
+        #[cfg(feature = "tracing-spans")]
+        let _span = tracing::debug_span!("run_once", revision = revision.0).entered();
+        self.recorder.set_revision(revision);
 
-        let ret = self.context_handle().offer(|| topo::call(op));
+        let ret = self.effects.defer(|| {
+            let (roots, cache) = (&self.roots, &self.revisions.cache);
+            self.revisions.run_and_gc(|| {
+                self.context_handle().offer(|| {
+                    roots.run(cache, should_run);
+                    topo::call(op)
+                })
+            })
+        });
+        self.roots.mark_ran(revision, should_run);
 
-        self.cache.gc();
+        self.effects.flush();
+        // effects can touch the cache too, so their use is part of this revision
+        self.revisions.finish();
         ret
     }
 
+    /// Adds a root function named `name` which is run by
+    /// [`Runtime::run_roots`] and [`Runtime::run_root`], replacing any root
+    /// which already has that name. Each root runs in its own call tree, so
+    /// roots don't share any state or cached values unless they're passed
+    /// between them, but all of them share the runtime's cache, executors, and
+    /// revisions.
+    ///
+    /// The values cached by a root are only garbage collected at the end of
+    /// revisions in which it ran, so roots can be run as often as they need
+    /// to be, and revisions run by [`Runtime::run_once`] keep all of them.
+    /// Values cached by a root which was replaced are kept until it next runs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{runtime::Runtime, state};
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let mut rt = Runtime::new();
+    /// let (main_runs, panel_runs) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    ///
+    /// let runs = main_runs.clone();
+    /// rt.add_root("main", move || {
+    ///     let (count, key) = state(|| 0);
+    ///     runs.set(*count + 1);
+    ///     key.set(*count + 1);
+    /// });
+    /// let runs = panel_runs.clone();
+    /// rt.add_root("panel", move || {
+    ///     let (count, key) = state(|| 0);
+    ///     runs.set(*count + 1);
+    ///     key.set(*count + 1);
+    /// });
+    ///
+    /// rt.run_roots();
+    /// rt.run_root("main");
+    /// rt.run_root("main");
+    /// assert_eq!((main_runs.get(), panel_runs.get()), (3, 1));
+    ///
+    /// rt.run_roots();
+    /// assert_eq!((main_runs.get(), panel_runs.get()), (4, 2), "panel's state was kept");
+    /// assert_eq!(rt.revision().0, 4);
+    /// ```
+    pub fn add_root(&mut self, name: &str, root: impl FnMut() + 'static) {
+        self.roots.insert(name, root);
+    }
+
+    /// Removes the root named `name`, returning whether it existed. The
+    /// values it cached are dropped at the end of the next revision.
+    pub fn remove_root(&mut self, name: &str) -> bool {
+        self.roots.remove(name)
+    }
+
+    /// Runs all roots added with [`Runtime::add_root`] during a single
+    /// revision, in the order they were added. Otherwise behaves like
+    /// [`Runtime::run_once`].
+    pub fn run_roots(&mut self) {
+        self.run_revision(&|_| true, || ())
+    }
+
+    /// Runs only the root named `name` during a new revision, keeping the
+    /// values cached by the other roots. Otherwise behaves like
+    /// [`Runtime::run_once`].
+    ///
+    /// # Panics
+    ///
+    /// If no root named `name` has been added.
+    pub fn run_root(&mut self, name: &str) {
+        assert!(self.roots.contains(name), "no root named `{}` has been added", name);
+        self.run_revision(&|root| root == name, || ())
+    }
+
     /// Sets the [`std::task::Waker`] which will be called when state variables
     /// receive commits. By default the runtime no-ops on a state change,
     /// which is probably the desired behavior if the embedding system will
     /// call `Runtime::run_once` on a regular interval regardless.
     pub fn set_state_change_waker(&mut self, wk: Waker) {
-        self.wk = wk;
+        self.revisions.set_state_change_waker(wk);
     }
 
     /// Sets the executor that will be used to spawn normal priority tasks.
     pub fn set_task_executor(&mut self, sp: impl LocalSpawn + 'static) {
-        self.spawner = Spawner(Rc::new(sp));
+        self.spawner.set(Priority::Normal, Rc::new(sp));
+    }
+
+    /// Sets the executor that will be used to spawn tasks of the given
+    /// `priority`. Until this is called for a priority, its tasks are spawned
+    /// onto the normal priority executor.
+    pub fn set_priority_executor(&mut self, priority: Priority, sp: impl LocalSpawn + 'static) {
+        self.spawner.set(priority, Rc::new(sp));
+    }
+
+    /// Sets the [`Clock`] used for timers, such as [`crate::timeout`],
+    /// [`crate::interval`], and the timeouts and retry backoff of
+    /// [`crate::load_with_policy`]. No clock is provided by default.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = RuntimeClock(Some(Rc::new(clock)));
+    }
+
+    /// Includes state variables of type `T` in the snapshots returned by
+    /// [`Runtime::snapshot_state`], and allows them to be restored with
+    /// [`Runtime::restore_state`].
+    ///
+    /// Types must be registered before the state variables which should be
+    /// restored are first declared.
+    #[cfg(feature = "serde")]
+    pub fn persist_state<T>(&mut self)
+    where
+        T: serde_crate::de::DeserializeOwned + serde_crate::Serialize + 'static,
+    {
+        self.persistence.register::<T>();
+    }
+
+    /// Returns the latest values of all live state variables whose types were
+    /// registered with [`Runtime::persist_state`], including commits which
+    /// are still pending.
+    #[cfg(feature = "serde")]
+    pub fn snapshot_state(&self) -> StateSnapshot {
+        self.persistence.snapshot()
+    }
+
+    /// Seeds state variables from a snapshot, usually one taken by a previous
+    /// execution of the same program. When a state variable of a registered
+    /// type is declared at a callsite whose [`topo::CallId::stable_id`] is in
+    /// the snapshot, its initializer is skipped and the restored value is used
+    /// instead. Values which fail to deserialize fall back to the initializer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{runtime::RunLoop, state, Commit, Key};
+    ///
+    /// fn root() -> (Commit<u32>, Key<u32>) {
+    ///     state(|| 0)
+    /// }
+    ///
+    /// let mut rt = RunLoop::new(root);
+    /// rt.persist_state::<u32>();
+    ///
+    /// let (_, key) = rt.run_once();
+    /// key.set(5);
+    /// let snapshot = rt.snapshot_state();
+    /// assert_eq!(snapshot.len(), 1);
+    ///
+    /// // later, after the program has been reloaded
+    /// let mut rt = RunLoop::new(root);
+    /// rt.persist_state::<u32>();
+    /// rt.restore_state(snapshot);
+    ///
+    /// let (restored, _) = rt.run_once();
+    /// assert_eq!(*restored, 5);
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn restore_state(&mut self, snapshot: StateSnapshot) {
+        self.persistence.seed(snapshot);
     }
 }
 
+impl Runtime {
+    /// Records every commit made to state variables of type `T`, including
+    /// commits which are overwritten before the next revision. The recorded
+    /// commits are returned by [`Runtime::commit_log`] and allow
+    /// [`Runtime::travel_to`] to step those variables back and forth between
+    /// revisions.
+    ///
+    /// Types must be registered before the state variables which should be
+    /// recorded are first declared. Recorded values are retained for as long as
+    /// their state variables are alive, so this is intended for debugging.
+    pub fn record_commits<T: Debug + 'static>(&mut self) {
+        self.recorder.register::<T>();
+    }
+
+    /// Returns all commits recorded since [`Runtime::record_commits`] was
+    /// called, in the order they were made.
+    pub fn commit_log(&self) -> Vec<CommitRecord> {
+        self.recorder.commits()
+    }
+
+    /// Enqueues commits to each live, recorded state variable which restore the
+    /// values they had at the start of `revision`. The next call to
+    /// [`Runtime::run_once`] will observe the restored values. The log is left
+    /// intact, so it's possible to step forward again to a later revision.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{
+    ///     runtime::{Revision, RunLoop},
+    ///     state,
+    /// };
+    ///
+    /// let mut rt = RunLoop::new(|| state(|| 0));
+    /// rt.record_commits::<i32>();
+    ///
+    /// let (_, count) = rt.run_once();
+    /// count.update(|c| Some(c + 1));
+    /// count.update(|c| Some(c + 1));
+    /// assert_eq!(*rt.run_once().0, 2);
+    /// assert_eq!(rt.commit_log().len(), 2);
+    ///
+    /// rt.travel_to(Revision(1));
+    /// assert_eq!(*rt.run_once().0, 0);
+    ///
+    /// rt.travel_to(Revision(2));
+    /// assert_eq!(*rt.run_once().0, 2);
+    /// ```
+    pub fn travel_to(&mut self, revision: Revision) {
+        self.recorder.travel_to(revision);
+    }
+
+    /// Returns counts of the work done during the latest revision: cache hits,
+    /// misses, and evictions for each type of cached value, commits to state
+    /// variables and the wakes they caused, and load tasks spawned and aborted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{cache, runtime::RunLoop, state};
+    ///
+    /// let mut rt = RunLoop::new(|| {
+    ///     let (count, key) = state(|| 1);
+    ///     (cache(&*count, |c| c * 2), key)
+    /// });
+    ///
+    /// let (_, key) = rt.run_once();
+    /// assert_eq!(rt.stats().cache_hits(), 0);
+    ///
+    /// key.set(2);
+    /// assert_eq!(rt.stats().commits(), 1);
+    ///
+    /// rt.run_once();
+    /// let stats = rt.stats();
+    /// assert_eq!((stats.cache_hits(), stats.cache_misses()), (1, 1), "state hit, cache missed");
+    /// assert_eq!(stats.commits(), 0);
+    /// ```
+    pub fn stats(&self) -> RevisionStats {
+        self.revisions.stats()
+    }
+
+    pub(crate) fn pending_commits(&self) -> &PendingCommits {
+        &self.revisions.pending_commits
+    }
+}
+
+/// The relative importance of a task spawned by the runtime, used to choose the
+/// executor it's spawned onto. See [`Runtime::set_priority_executor`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Priority {
+    /// Work the user is waiting on, like loading the result of an interaction.
+    UserBlocking,
+    /// The priority of tasks spawned by [`crate::load`] and related functions.
+    Normal,
+    /// Work which can wait until nothing else is pending, like prefetching.
+    Idle,
+}
+
 #[derive(Clone)]
-struct Spawner(pub Rc<dyn LocalSpawn>);
+struct Spawner {
+    user_blocking: Option<Rc<dyn LocalSpawn>>,
+    normal: Rc<dyn LocalSpawn>,
+    idle: Option<Rc<dyn LocalSpawn>>,
+}
+
+impl Spawner {
+    fn new() -> Self {
+        Self { user_blocking: None, normal: Rc::new(JunkSpawner), idle: None }
+    }
+
+    fn set(&mut self, priority: Priority, sp: Rc<dyn LocalSpawn>) {
+        match priority {
+            Priority::UserBlocking => self.user_blocking = Some(sp),
+            Priority::Normal => self.normal = sp,
+            Priority::Idle => self.idle = Some(sp),
+        }
+    }
+
+    /// Returns the executor for `priority`, falling back to the normal
+    /// priority executor.
+    fn get(&self, priority: Priority) -> &dyn LocalSpawn {
+        let specific = match priority {
+            Priority::UserBlocking => self.user_blocking.as_ref(),
+            Priority::Normal => None,
+            Priority::Idle => self.idle.as_ref(),
+        };
+        &**specific.unwrap_or(&self.normal)
+    }
+}
 
 impl Debug for Spawner {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        f.write_fmt(format_args!("{:p}", &self.0))
+        f.debug_struct("Spawner")
+            .field("user_blocking", &self.user_blocking.as_ref().map(Rc::as_ptr))
+            .field("normal", &Rc::as_ptr(&self.normal))
+            .field("idle", &self.idle.as_ref().map(Rc::as_ptr))
+            .finish()
     }
 }
 
+/// The executor used until one is provided, which refuses to spawn anything.
+/// Shared by [`Runtime`] and [`SendRuntime`].
 struct JunkSpawner;
 impl LocalSpawn for JunkSpawner {
     fn spawn_local_obj(&self, _: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
@@ -199,6 +579,16 @@ impl LocalSpawn for JunkSpawner {
     }
 }
 
+impl Spawn for JunkSpawner {
+    fn spawn_obj(&self, _: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+
+    fn status(&self) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -219,6 +609,34 @@ mod tests {
         assert!(illicit::get::<u8>().is_err());
     }
 
+    #[test]
+    fn effects_wait_for_root_to_return() {
+        let log = Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut rt = Runtime::new();
+        let mut run = |epoch: u8| {
+            rt.run_once(|| {
+                for name in &["first", "second"] {
+                    let log = log.clone();
+                    topo::call(|| {
+                        crate::effect(&epoch, move |_| {
+                            log.borrow_mut().push(format!("setup {}", name));
+                            move || log.borrow_mut().push(format!("cleanup {}", name))
+                        })
+                    });
+                }
+                log.borrow_mut().push(String::from("returning"));
+            });
+            log.replace(Vec::new())
+        };
+
+        assert_eq!(run(0), ["returning", "setup first", "setup second"]);
+        assert_eq!(run(0), ["returning"]);
+        assert_eq!(
+            run(1),
+            ["returning", "cleanup first", "cleanup second", "setup first", "setup second"]
+        );
+    }
+
     #[test]
     fn tick_a_few_times() {
         let mut rt = RunLoop::new(Revision::current);
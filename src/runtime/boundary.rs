@@ -0,0 +1,75 @@
+use super::Revision;
+use dyn_cache::local::SharedLocalCache;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use topo::CallId;
+
+/// The cache key of the anchor for the subtree of a [`crate::catch_boundary`].
+///
+/// The values cached by the boundary's child are anchored to a cache entry for
+/// the boundary. When the child returns, the anchor is re-initialized and the
+/// values it cached are retained only if they're used. When it panics, the
+/// anchor is only read, so the values which the child didn't reach before
+/// panicking inherit its liveness until it recovers.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct BoundaryAnchor(CallId);
+
+/// Run `child` in the subtree of the boundary at `id`, returning the panic
+/// payload if it panics. Must be called during `revision`.
+pub(crate) fn run_in_boundary<Ret>(
+    cache: &SharedLocalCache,
+    id: &CallId,
+    revision: &Revision,
+    child: impl FnOnce() -> Ret,
+) -> std::thread::Result<Ret> {
+    let mut ret = None;
+    catch_unwind(AssertUnwindSafe(|| {
+        cache.hold(&BoundaryAnchor(*id), revision, |_| ret = Some(child()));
+    }))?;
+    Ok(ret.expect("boundary anchors are re-initialized each revision they're held"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{catch_boundary, once, runtime::RunLoop, state};
+    use std::{cell::Cell, rc::Rc};
+
+    struct DropCounter(Rc<Cell<u32>>);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn failing_boundaries_dont_stop_collection() {
+        let (failed_drops, unrelated_drops) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let mut rt = RunLoop::new(|| {
+            let (fail, set_fail) = state(|| false);
+            let (show, set_show) = state(|| true);
+            if *show {
+                once(|| Rc::new(DropCounter(unrelated_drops.clone())));
+            }
+            catch_boundary(
+                || {
+                    assert!(!*fail, "failing");
+                    once(|| Rc::new(DropCounter(failed_drops.clone())));
+                },
+                |_| (),
+            );
+            (set_fail, set_show)
+        });
+
+        let (fail, show) = rt.run_once();
+        fail.set(true);
+        show.set(false);
+        for _ in 0..3 {
+            rt.run_once();
+            assert_eq!(unrelated_drops.get(), 1, "unrelated values are still collected");
+            assert_eq!(failed_drops.get(), 0, "the failed subtree's values are kept");
+        }
+
+        fail.set(false);
+        rt.run_once();
+        assert_eq!(failed_drops.get(), 0, "the recovered subtree reused its value");
+    }
+}
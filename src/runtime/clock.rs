@@ -0,0 +1,32 @@
+use futures::future::LocalBoxFuture;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    time::Duration,
+};
+
+/// A source of timers for a [`super::Runtime`], set with
+/// [`super::Runtime::set_clock`].
+///
+/// moxie doesn't depend on any particular timer implementation, so embedders
+/// provide one appropriate to their environment, such as a browser's
+/// `setTimeout` or an async runtime's sleep function. Tests can provide a clock
+/// whose time only advances when instructed.
+pub trait Clock {
+    /// Returns the time which has elapsed since a fixed point, such as when
+    /// the clock was created. Must never decrease.
+    fn now(&self) -> Duration;
+
+    /// Returns a future which completes once `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> LocalBoxFuture<'static, ()>;
+}
+
+/// The clock of a runtime, if one has been set.
+#[derive(Clone, Default)]
+pub(crate) struct RuntimeClock(pub Option<Rc<dyn Clock>>);
+
+impl Debug for RuntimeClock {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_fmt(format_args!("{:?}", self.0.as_ref().map(Rc::as_ptr)))
+    }
+}
@@ -1,11 +1,24 @@
-use super::{Revision, Spawner, Var};
+use super::{
+    boundary::run_in_boundary, clock::RuntimeClock, pending::PendingCommits, CachedVar, Clock,
+    Effects, LoadError, LoadPolicy, Priority, Provided, Reads, Recorder, Revision, Spawner,
+    StatsCounters, StoreSlot, Stores, Var,
+};
 use crate::{Commit, Key};
 use dyn_cache::local::SharedLocalCache;
-use futures::future::abortable;
+use futures::{
+    future::{abortable, ready, AbortHandle, FutureExt},
+    stream::{Stream, StreamExt},
+};
+use parking_lot::Mutex;
+use scopeguard::ScopeGuard;
 use std::{
     borrow::Borrow,
+    cell::Cell,
     future::Future,
+    rc::Rc,
+    sync::Arc,
     task::{Poll, Waker},
+    time::Duration,
 };
 
 /// A handle to the current [`Runtime`] which is offered via [`illicit`]
@@ -15,7 +28,18 @@ use std::{
 pub(crate) struct Context {
     revision: Revision,
     pub cache: SharedLocalCache,
+    effects: Effects,
+    #[cfg(feature = "serde")]
+    persistence: super::Persistence,
+    recorder: Recorder,
     spawner: Spawner,
+    clock: RuntimeClock,
+    pending_commits: PendingCommits,
+    stores: Stores,
+    stats: StatsCounters,
+    /// The number of `provide` calls on the stack, so that cached closures only
+    /// pay for tracking their reads when there's something to read.
+    providing: Cell<u32>,
     waker: Waker,
 }
 
@@ -25,8 +49,66 @@ impl Context {
         self.revision
     }
 
+    /// Run `child`, calling `fallback` with the panic if it panics.
+    pub fn catch_boundary<Ret>(
+        &self,
+        id: &topo::CallId,
+        child: impl FnOnce() -> Ret,
+        fallback: impl FnOnce(&crate::Panic) -> Ret,
+    ) -> Ret {
+        match run_in_boundary(&self.cache, id, &self.revision, child) {
+            Ok(ret) => ret,
+            Err(payload) => fallback(&crate::Panic::new(payload)),
+        }
+    }
+
+    /// Cache the result of `init(arg)`, re-running it when `arg` changes or
+    /// when a value it consumed from a [`crate::provide`] call has changed.
+    pub fn cache_with<Arg, Input, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Output: 'static,
+        Ret: 'static,
+    {
+        if self.providing.get() == 0 {
+            // nothing can be consumed, so there's no need to track it
+            return self.cache.cache_with(id, arg, init, with);
+        }
+
+        let reads: Reads = self.cache.cache(id, &(), |()| Reads::default());
+        let key = (*id, reads.generation());
+        let ret = self.cache.cache_with(&key, arg, |i| reads.track_provided(|| init(i)), with);
+        reads.forward();
+        ret
+    }
+
+    /// Offer `value` to `child` for [`crate::consume`], invalidating the
+    /// values which consumed it whenever it changes.
+    pub fn provide<T, Ret>(&self, id: &topo::CallId, value: T, child: impl FnOnce() -> Ret) -> Ret
+    where
+        T: PartialEq + 'static,
+    {
+        let mut value = Some(value);
+        let provided = self.cache.cache(id, &(), |()| Provided::new(value.take().unwrap()));
+        if let Some(value) = value {
+            provided.update(value);
+        }
+        self.providing.set(self.providing.get() + 1);
+        let _providing = scopeguard::guard((), |()| self.providing.set(self.providing.get() - 1));
+        illicit::Layer::new().offer(provided).enter(child)
+    }
+
     /// Load a [`crate::state::Var`] with the provided argument and initializer.
-    /// Re-initializes the `Var` whenever `arg` changes.
+    /// Re-initializes the `Var` whenever `arg` changes. If the runtime was
+    /// seeded with a value for this callsite, it is used instead of calling
+    /// `init`.
     pub fn cache_state<Arg, Input, Output>(
         &self,
         id: &topo::CallId,
@@ -38,17 +120,81 @@ impl Context {
         Input: Borrow<Arg> + 'static,
         Output: 'static,
     {
-        let var = self
-            .cache
-            .cache(id, arg, |arg| Var::new(topo::CallId::current(), self.waker.clone(), init(arg)));
+        let var = self.cache.cache_with(
+            id,
+            arg,
+            |arg| CachedVar(self.new_var(topo::CallId::current(), || init(arg))),
+            |cached| cached.0.clone(),
+        );
         Var::root(var)
     }
 
+    /// Load the runtime-wide state variable for `State`, initializing it with
+    /// `State::default()` the first time it's requested.
+    pub fn store<State>(&self) -> (Commit<State>, Key<State>)
+    where
+        State: Default + 'static,
+    {
+        let var = self.stores.get_or_insert_with(|| {
+            let id =
+                topo::root(|| topo::call_in_slot(&StoreSlot::of::<State>(), topo::CallId::current));
+            self.new_var(id, State::default)
+        });
+        Var::root(var)
+    }
+
+    /// Create a new state variable declared at `id`, using a value restored
+    /// from persisted state if one was seeded instead of calling `init`.
+    fn new_var<State: 'static>(
+        &self,
+        id: topo::CallId,
+        init: impl FnOnce() -> State,
+    ) -> Arc<Mutex<Var<State>>> {
+        #[cfg(feature = "serde")]
+        let initial = self.persistence.restore(id).unwrap_or_else(init);
+        #[cfg(not(feature = "serde"))]
+        let initial = init();
+
+        let var = Var::new(id, self.waker.clone(), self.pending_commits.clone(), initial);
+        #[cfg(feature = "serde")]
+        self.persistence.track(id, &var);
+        self.recorder.track(&var);
+        var
+    }
+
+    /// Cache the result of `op`, re-running it only when one of the state
+    /// variables it rooted has received a new commit since it last ran.
+    pub fn derived<Output>(&self, id: &topo::CallId, op: impl FnOnce() -> Output) -> Output
+    where
+        Output: Clone + 'static,
+    {
+        let reads: Reads = self.cache.cache(id, &(), |()| Reads::default());
+        let output = self.cache.cache(id, &reads.generation(), |_| reads.track(op));
+        reads.forward();
+        output
+    }
+
+    /// Schedule `setup` to run after the current revision whenever `arg`
+    /// changes. The cleanup returned by `setup` runs after the revision in
+    /// which `arg` changes again or this callsite is no longer called.
+    pub fn effect<Arg, Input, Cleanup>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        setup: impl FnOnce(&Input) -> Cleanup + 'static,
+    ) where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Cleanup: FnOnce() + 'static,
+    {
+        self.cache.hold(id, arg, |_| self.effects.schedule(arg.to_owned(), setup));
+    }
+
     /// Load a value from the future returned by `init` whenever `capture`
     /// changes, returning the result of calling `with` with the loaded
     /// value. Cancels the running future if there's no longer interest
     /// in its output, indicated by a revision in which this was not called with
-    /// the given `id`.
+    /// the given `id`. New futures are spawned onto the executor for `priority`.
     ///
     /// # Panics
     ///
@@ -57,6 +203,157 @@ impl Context {
     pub fn load_with<Arg, Input, Fut, Output, Ret>(
         &self,
         id: &topo::CallId,
+        priority: Priority,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Fut,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Poll<Ret>
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Fut: Future<Output = Output> + 'static,
+        Output: 'static,
+        Ret: 'static,
+    {
+        self.spawn_load(id, priority, false, arg, init, with)
+    }
+
+    /// Load a value like [`Context::load_with`], running the futures returned
+    /// by `init` according to `policy`.
+    ///
+    /// # Panics
+    ///
+    /// If the [`super::Runtime`] from which `self` was created did not have
+    /// a valid call to `set_task_executor`, or did not have a valid call to
+    /// `set_clock` and `policy` uses the clock.
+    pub fn load_with_policy<Arg, Input, Fut, Output, Error, Ret>(
+        &self,
+        id: &topo::CallId,
+        policy: &LoadPolicy,
+        arg: &Arg,
+        mut init: impl FnMut(&Input) -> Fut + 'static,
+        with: impl FnOnce(&Result<Output, LoadError<Error>>) -> Ret,
+    ) -> Poll<Ret>
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + Clone + 'static,
+        Fut: Future<Output = Result<Output, Error>> + 'static,
+        Output: 'static,
+        Error: 'static,
+        Ret: 'static,
+    {
+        if policy.uses_clock() {
+            self.clock.0.as_ref().expect("that set_clock has been called");
+        }
+        let (keep_stale, policy, clock) =
+            (policy.keeps_stale(), policy.clone(), self.clock.0.clone());
+        let init = move |input: &Input| {
+            let input = input.clone();
+            policy.run(clock, move || init(&input))
+        };
+        self.spawn_load(id, Priority::Normal, keep_stale, arg, init, with)
+    }
+
+    /// Spawn the stream returned by `init` whenever `arg` changes, committing
+    /// each of its items to a state variable which starts as `initial`.
+    /// Returns the latest item. Cancels the running stream if there's no longer
+    /// interest in its output.
+    ///
+    /// # Panics
+    ///
+    /// If the [`super::Runtime`] from which `self` was created did not have
+    /// a valid call to `set_task_executor`.
+    pub fn stream_latest<Arg, Input, S, Item>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> S,
+        initial: Item,
+    ) -> Item
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        S: Stream<Item = Item> + 'static,
+        Item: Clone + 'static,
+    {
+        // both are re-initialized whenever arg changes
+        let (latest, set_latest) = self.cache_state(id, arg, |_| initial);
+        self.cache.hold(id, arg, |arg| {
+            self.spawn_abortable(init(arg).for_each(move |item| {
+                set_latest.update(|_| Some(item));
+                ready(())
+            }))
+        });
+        (*latest).clone()
+    }
+
+    /// Returns true once `duration` has elapsed since the first revision in
+    /// which this was called with the same `duration`.
+    ///
+    /// # Panics
+    ///
+    /// If the [`super::Runtime`] from which `self` was created did not have
+    /// valid calls to `set_task_executor` and `set_clock`.
+    pub fn timeout(&self, id: &topo::CallId, duration: Duration) -> bool {
+        let (elapsed, set_elapsed) = self.cache_state(id, &duration, |_| false);
+        self.cache.hold(id, &duration, |&duration| {
+            let sleep = self.clock().sleep(duration);
+            self.spawn_abortable(async move {
+                sleep.await;
+                set_elapsed.set(true);
+            })
+        });
+        *elapsed
+    }
+
+    /// Returns the number of times `period` has elapsed since the first
+    /// revision in which this was called with the same `period`.
+    ///
+    /// # Panics
+    ///
+    /// If `period` is zero, or if the [`super::Runtime`] from which `self` was
+    /// created did not have valid calls to `set_task_executor` and
+    /// `set_clock`.
+    pub fn interval(&self, id: &topo::CallId, period: Duration) -> u64 {
+        assert!(period > Duration::from_secs(0), "interval() requires a non-zero period");
+        let (ticks, set_ticks) = self.cache_state(id, &period, |_| 0);
+        self.cache.hold(id, &period, |&period| {
+            let clock = self.clock();
+            let mut deadline = clock.now();
+            self.spawn_abortable(async move {
+                loop {
+                    // measure from the last deadline so that late wakeups don't delay later ticks
+                    deadline += period;
+                    clock.sleep(deadline.checked_sub(clock.now()).unwrap_or_default()).await;
+                    set_ticks.update(|t| Some(t + 1));
+                }
+            })
+        });
+        *ticks
+    }
+
+    fn clock(&self) -> Rc<dyn Clock> {
+        self.clock.0.clone().expect("that set_clock has been called")
+    }
+
+    /// Spawns `task`, returning a guard which cancels it when dropped.
+    fn spawn_abortable(
+        &self,
+        task: impl Future<Output = ()> + 'static,
+    ) -> ScopeGuard<AbortHandle, impl FnOnce(AbortHandle)> {
+        let (task, aborter) = abortable(task);
+        self.spawner
+            .get(Priority::Normal)
+            .spawn_local_obj(Box::pin(task.map(drop)).into())
+            .expect("that set_task_executor has been called");
+        scopeguard::guard(aborter, |a| a.abort())
+    }
+
+    fn spawn_load<Arg, Input, Fut, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        priority: Priority,
+        keep_stale: bool,
         arg: &Arg,
         init: impl FnOnce(&Input) -> Fut,
         with: impl FnOnce(&Output) -> Ret,
@@ -71,20 +368,34 @@ impl Context {
         let (_, set_result): (_, Key<Poll<Output>>) = self.cache_state(id, &(), |()| Poll::Pending);
         let mut set_result2 = set_result.clone();
         self.cache.hold(id, arg, |arg| {
-            // before we spawn the new task we need to mark it pending
-            set_result.force(Poll::Pending);
+            // before we spawn the new task we need to mark it pending, unless the
+            // previous result should be shown until the new one is ready
+            if !keep_stale {
+                set_result.force(Poll::Pending);
+            }
 
             let (fut, aborter) = abortable(init(arg));
+            let finished = Rc::new(Cell::new(false));
+            let set_finished = finished.clone();
             let task = async move {
                 if let Ok(to_store) = fut.await {
                     set_result.update(|_| Some(Poll::Ready(to_store)));
                 }
+                set_finished.set(true);
             };
             self.spawner
-                .0
+                .get(priority)
                 .spawn_local_obj(Box::pin(task).into())
                 .expect("that set_task_executor has been called");
-            scopeguard::guard(aborter, |a| a.abort())
+            self.stats.load_spawned();
+
+            let stats = self.stats.clone();
+            scopeguard::guard(aborter, move |a| {
+                if !finished.get() {
+                    stats.load_aborted();
+                }
+                a.abort()
+            })
         });
 
         set_result2.refresh();
@@ -98,11 +409,21 @@ impl Context {
 
 impl super::Runtime {
     pub(crate) fn context_handle(&self) -> Context {
+        let revisions = &self.revisions;
         Context {
-            revision: self.revision,
+            revision: revisions.revision,
             spawner: self.spawner.clone(),
-            cache: self.cache.clone(),
-            waker: self.wk.clone(),
+            cache: revisions.cache.clone(),
+            effects: self.effects.clone(),
+            #[cfg(feature = "serde")]
+            persistence: self.persistence.clone(),
+            recorder: self.recorder.clone(),
+            clock: self.clock.clone(),
+            pending_commits: revisions.pending_commits.clone(),
+            stores: self.stores.clone(),
+            stats: revisions.stats.clone(),
+            providing: Cell::new(0),
+            waker: revisions.waker.clone(),
         }
     }
 }
@@ -0,0 +1,109 @@
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem::take,
+    rc::Rc,
+};
+
+/// The queue of effect setups and cleanups for a [`super::Runtime`]. Work is
+/// deferred while a revision is executing and run in [`Effects::flush`].
+#[derive(Clone, Default)]
+pub(crate) struct Effects {
+    inner: Rc<RefCell<InnerEffects>>,
+}
+
+#[derive(Default)]
+struct InnerEffects {
+    deferring: bool,
+    cleanups: Vec<Box<dyn FnOnce()>>,
+    setups: Vec<Box<dyn FnOnce()>>,
+}
+
+impl Effects {
+    /// Run `op`, deferring any cleanups it triggers until the next call to
+    /// [`Effects::flush`].
+    pub fn defer<R>(&self, op: impl FnOnce() -> R) -> R {
+        self.inner.borrow_mut().deferring = true;
+        let _stop_deferring = scopeguard::guard(self.inner.clone(), |inner| {
+            inner.borrow_mut().deferring = false;
+        });
+        op()
+    }
+
+    /// Runs all pending cleanups in the order they were triggered, then all
+    /// pending setups in the order they were scheduled.
+    pub fn flush(&self) {
+        let cleanups = take(&mut self.inner.borrow_mut().cleanups);
+        cleanups.into_iter().for_each(|cleanup| cleanup());
+
+        let setups = take(&mut self.inner.borrow_mut().setups);
+        setups.into_iter().for_each(|setup| setup());
+    }
+
+    /// Prepare an effect for its setup to be run at the next flush, returning
+    /// a guard which will run its cleanup when dropped.
+    pub fn schedule<Input, Cleanup>(
+        &self,
+        input: Input,
+        setup: impl FnOnce(&Input) -> Cleanup + 'static,
+    ) -> EffectGuard
+    where
+        Input: 'static,
+        Cleanup: FnOnce() + 'static,
+    {
+        let state = Rc::new(RefCell::new(EffectState::Pending));
+        let to_set = state.clone();
+        self.inner.borrow_mut().setups.push(Box::new(move || {
+            let mut state = to_set.borrow_mut();
+            if let EffectState::Pending = &*state {
+                *state = EffectState::Active(Box::new(setup(&input)));
+            }
+        }));
+        EffectGuard { effects: self.clone(), state }
+    }
+
+    fn cleanup(&self, cleanup: Box<dyn FnOnce()>) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.deferring {
+            inner.cleanups.push(cleanup);
+        } else {
+            drop(inner);
+            cleanup();
+        }
+    }
+}
+
+impl Debug for Effects {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Effects")
+            .field("deferring", &inner.deferring)
+            .field("cleanups", &inner.cleanups.len())
+            .field("setups", &inner.setups.len())
+            .finish()
+    }
+}
+
+enum EffectState {
+    /// The setup function has not yet run.
+    Pending,
+    /// The setup function has run and returned a cleanup function.
+    Active(Box<dyn FnOnce()>),
+    /// The effect was dropped before its setup function could run.
+    Dropped,
+}
+
+/// Stored in the cache at an effect's callsite, runs the effect's cleanup when
+/// dropped.
+pub(crate) struct EffectGuard {
+    effects: Effects,
+    state: Rc<RefCell<EffectState>>,
+}
+
+impl Drop for EffectGuard {
+    fn drop(&mut self) {
+        if let EffectState::Active(cleanup) = self.state.replace(EffectState::Dropped) {
+            self.effects.cleanup(cleanup);
+        }
+    }
+}
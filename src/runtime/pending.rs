@@ -0,0 +1,164 @@
+use super::transaction::TxnStatus;
+use parking_lot::Mutex;
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::Arc,
+};
+use topo::CallId;
+
+/// The callsites of state variables which have received commits since the
+/// start of a runtime's latest revision. Shared with state variables, which may
+/// receive commits from other threads.
+///
+/// Commits made within a transaction are only reported once it has finished,
+/// and are forgotten if it aborts.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PendingCommits {
+    inner: Arc<Mutex<Vec<PendingCommit>>>,
+}
+
+/// The number of commits made to the state variable declared at `id`, either
+/// within the running transaction with status `txn` or visibly if it's `None`.
+/// There's at most one entry for each pair, so the list doesn't grow with the
+/// number of commits.
+#[derive(Debug)]
+struct PendingCommit {
+    id: CallId,
+    txn: Option<TxnStatus>,
+    commits: u64,
+}
+
+impl PendingCommits {
+    /// Record a commit to the state variable declared at `id`, made within the
+    /// transaction with status `txn` if any.
+    pub fn push(&self, id: CallId, txn: Option<&TxnStatus>) {
+        let mut inner = self.inner.lock();
+        settle(&mut inner);
+        let txn = txn.filter(|txn| !txn.is_committed());
+        let existing = inner.iter_mut().find(|pending| {
+            pending.id == id
+                && match (&pending.txn, txn) {
+                    (Some(pending), Some(txn)) => pending.ptr_eq(txn),
+                    (pending, txn) => pending.is_none() && txn.is_none(),
+                }
+        });
+        match existing {
+            Some(pending) => pending.commits += 1,
+            None => inner.push(PendingCommit { id, txn: txn.cloned(), commits: 1 }),
+        }
+    }
+
+    /// Forget all recorded commits, called at the start of each revision.
+    pub fn clear(&self) {
+        self.inner.lock().clear();
+    }
+
+    /// Returns the callsites which received visible commits, in the order of
+    /// their first commit.
+    pub fn callsites(&self) -> Vec<CallId> {
+        let mut inner = self.inner.lock();
+        settle(&mut inner);
+        inner.iter().filter(|pending| pending.txn.is_none()).map(|pending| pending.id).collect()
+    }
+
+    /// Returns the number of visible commits made.
+    pub fn len(&self) -> u64 {
+        let mut inner = self.inner.lock();
+        settle(&mut inner);
+        inner.iter().filter(|pending| pending.txn.is_none()).map(|pending| pending.commits).sum()
+    }
+
+    /// Returns true if no visible commits have been made.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Forgets the commits of aborted transactions and merges those of finished
+/// transactions into the visible commits to the same state variables, keeping
+/// the position of the earliest.
+fn settle(inner: &mut Vec<PendingCommit>) {
+    inner.retain(|pending| !TxnStatus::is_discarded(pending.txn.as_ref()));
+    let mut i = 0;
+    while i < inner.len() {
+        if TxnStatus::is_visible(inner[i].txn.as_ref()) {
+            inner[i].txn = None;
+            let id = inner[i].id;
+            let earlier = inner[..i].iter().position(|p| p.id == id && p.txn.is_none());
+            if let Some(earlier) = earlier {
+                let merged = inner.remove(i);
+                inner[earlier].commits += merged.commits;
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Returned by [`super::RunLoop::run_until_stable`] when state variables are
+/// still receiving commits after the maximum number of revisions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unstable {
+    revisions: u64,
+    committing: Vec<CallId>,
+}
+
+impl Unstable {
+    pub(crate) fn new(revisions: u64, committing: Vec<CallId>) -> Self {
+        Self { revisions, committing }
+    }
+
+    /// The number of revisions which were run.
+    pub fn revisions(&self) -> u64 {
+        self.revisions
+    }
+
+    /// The callsites of the state variables which received commits during the
+    /// last revision that was run.
+    pub fn committing(&self) -> &[CallId] {
+        &self.committing
+    }
+}
+
+impl Display for Unstable {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "state still changing after {} revisions, committed to by {:?}",
+            self.revisions, self.committing
+        )
+    }
+}
+
+impl Error for Unstable {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Transaction;
+    use futures::task::noop_waker;
+
+    #[test]
+    fn repeated_commits_share_an_entry() {
+        let pending = PendingCommits::default();
+        let first = topo::call_in_slot(&1, CallId::current);
+        let second = topo::call_in_slot(&2, CallId::current);
+        for _ in 0..100 {
+            pending.push(first, None);
+            pending.push(second, None);
+        }
+        Transaction::run(|| {
+            let txn = Transaction::current().unwrap().stage(&noop_waker());
+            for _ in 0..100 {
+                pending.push(first, Some(&txn));
+            }
+            assert_eq!(pending.inner.lock().len(), 3);
+            assert_eq!(pending.len(), 200, "running transactions aren't counted");
+        });
+
+        assert_eq!(pending.len(), 300);
+        assert_eq!(pending.inner.lock().len(), 2, "merged once the transaction finished");
+        assert_eq!(pending.callsites(), [first, second]);
+    }
+}
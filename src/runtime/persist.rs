@@ -0,0 +1,204 @@
+use super::Var;
+use parking_lot::Mutex;
+use serde_crate::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_value::Value;
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::Arc,
+};
+use topo::CallId;
+
+/// The values of a [`super::Runtime`]'s state variables at a point in time,
+/// keyed by the [`CallId::stable_id`] of the callsite which declared each
+/// variable.
+///
+/// Only state variables whose types were registered with
+/// [`super::Runtime::persist_state`] are included. Snapshots implement
+/// `Serialize` and `Deserialize` so they can be written to storage and read
+/// back by a later execution of the same program.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct StateSnapshot {
+    vars: BTreeMap<u64, Value>,
+}
+
+impl StateSnapshot {
+    /// Returns the number of state variables in the snapshot.
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    /// Returns true if the snapshot contains no state variables.
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+}
+
+/// Tracks the state variables of a runtime which can be snapshotted, and the
+/// values with which to seed new state variables.
+#[derive(Clone, Default)]
+pub(crate) struct Persistence {
+    inner: Rc<RefCell<InnerPersistence>>,
+}
+
+#[derive(Default)]
+struct InnerPersistence {
+    codecs: HashMap<TypeId, Codec>,
+    seeds: BTreeMap<u64, Value>,
+    live: BTreeMap<u64, SaveVar>,
+}
+
+/// Returns `None` once the variable has been dropped, otherwise the saved value
+/// if serialization succeeded.
+type SaveVar = Box<dyn Fn() -> Option<Option<Value>>>;
+
+#[derive(Clone, Copy)]
+struct Codec {
+    save: fn(&dyn Any) -> Option<Value>,
+    restore: fn(Value) -> Option<Box<dyn Any>>,
+}
+
+impl Codec {
+    fn of<T>() -> Self
+    where
+        T: DeserializeOwned + Serialize + 'static,
+    {
+        Self {
+            save: |value| serde_value::to_value(value.downcast_ref::<T>()?).ok(),
+            restore: |value| Some(Box::new(value.deserialize_into::<T>().ok()?)),
+        }
+    }
+}
+
+impl Persistence {
+    /// Include state variables of type `T` in snapshots and seed them from
+    /// restored snapshots.
+    pub fn register<T>(&self)
+    where
+        T: DeserializeOwned + Serialize + 'static,
+    {
+        self.inner.borrow_mut().codecs.insert(TypeId::of::<T>(), Codec::of::<T>());
+    }
+
+    /// Returns the restored value for a state variable of type `T` declared at
+    /// `id`, if one was seeded and can be deserialized. Each seed is only used
+    /// once.
+    pub fn restore<T: 'static>(&self, id: CallId) -> Option<T> {
+        let mut inner = self.inner.borrow_mut();
+        let codec = *inner.codecs.get(&TypeId::of::<T>())?;
+        let seed = inner.seeds.remove(&id.stable_id())?;
+        (codec.restore)(seed)?.downcast().ok().map(|restored| *restored)
+    }
+
+    /// Include `var` in future snapshots if its type was registered.
+    pub fn track<T: 'static>(&self, id: CallId, var: &Arc<Mutex<Var<T>>>) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(codec) = inner.codecs.get(&TypeId::of::<T>()).copied() {
+            let var = Arc::downgrade(var);
+            let save = move || {
+                let var = var.upgrade()?;
+                let var = var.lock();
+                Some((codec.save)(var.latest()))
+            };
+            inner.live.insert(id.stable_id(), Box::new(save));
+        }
+    }
+
+    /// Replace any pending seeds with the contents of `snapshot`.
+    pub fn seed(&self, snapshot: StateSnapshot) {
+        self.inner.borrow_mut().seeds = snapshot.vars;
+    }
+
+    /// Save the latest values of all live, registered state variables.
+    pub fn snapshot(&self) -> StateSnapshot {
+        let mut vars = BTreeMap::new();
+        self.inner.borrow_mut().live.retain(|id, save| {
+            if let Some(saved) = save() {
+                if let Some(value) = saved {
+                    vars.insert(*id, value);
+                }
+                true
+            } else {
+                false
+            }
+        });
+        StateSnapshot { vars }
+    }
+}
+
+impl Debug for Persistence {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Persistence")
+            .field("codecs", &inner.codecs.len())
+            .field("seeds", &inner.seeds.len())
+            .field("live", &inner.live.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{runtime::RunLoop, state};
+
+    #[test]
+    fn only_registered_live_vars_are_saved() {
+        let mut rt = RunLoop::new(|| {
+            let (_, number) = state(|| 1u8);
+            let (_, text) = state(|| String::from("hello"));
+            if *number == 1 {
+                state(|| 10u16);
+            }
+            (number, text)
+        });
+        rt.persist_state::<u8>();
+        rt.persist_state::<u16>();
+
+        let (number, _) = rt.run_once();
+        assert_eq!(rt.snapshot_state().len(), 2, "String isn't registered");
+
+        number.set(2);
+        rt.run_once();
+        assert_eq!(rt.snapshot_state().len(), 1, "the u16 was dropped");
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        fn root() -> crate::Key<Vec<String>> {
+            state(Vec::new).1
+        }
+
+        let mut rt = RunLoop::new(root);
+        rt.persist_state::<Vec<String>>();
+        rt.run_once().update(|_| Some(vec![String::from("restored")]));
+
+        let serialized = serde_value::to_value(rt.snapshot_state()).unwrap();
+
+        let mut rt = RunLoop::new(root);
+        rt.persist_state::<Vec<String>>();
+        rt.restore_state(serialized.deserialize_into().unwrap());
+        assert_eq!(*rt.run_once(), ["restored"]);
+    }
+
+    #[test]
+    fn mismatched_types_are_initialized() {
+        // generic so that both types of state are declared at the same callsite
+        fn root<T: 'static>(init: fn() -> T) -> crate::Key<T> {
+            state(init).1
+        }
+
+        let mut rt = RunLoop::new(|| root(|| 1u8));
+        rt.persist_state::<u8>();
+        rt.run_once().set(2);
+        let snapshot = rt.snapshot_state();
+
+        let mut rt = RunLoop::new(|| root(|| vec![1u8]));
+        rt.persist_state::<Vec<u8>>();
+        rt.restore_state(snapshot);
+        assert_eq!(*rt.run_once(), [1]);
+    }
+}
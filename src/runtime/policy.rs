@@ -0,0 +1,209 @@
+use super::Clock;
+use futures::future::{select, Either};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    future::Future,
+    rc::Rc,
+    time::Duration,
+};
+
+/// Controls how [`crate::load_with_policy`] runs its futures.
+///
+/// By default a policy behaves like [`crate::load_with`]: it runs each future
+/// once without a time limit and resets to `Poll::Pending` when its argument
+/// changes.
+///
+/// # Example
+///
+/// ```
+/// use moxie::runtime::LoadPolicy;
+/// use std::time::Duration;
+///
+/// let policy = LoadPolicy::new()
+///     .stale_while_revalidate()
+///     .retry(3, Duration::from_millis(100))
+///     .timeout(Duration::from_secs(5));
+/// assert!(policy.uses_clock());
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LoadPolicy {
+    keep_stale: bool,
+    retries: u32,
+    backoff: Duration,
+    timeout: Option<Duration>,
+}
+
+impl LoadPolicy {
+    /// Returns the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep returning the previously loaded value while the future for a new
+    /// argument runs, rather than returning `Poll::Pending`.
+    pub fn stale_while_revalidate(mut self) -> Self {
+        self.keep_stale = true;
+        self
+    }
+
+    /// Retry failed or timed out attempts up to `retries` times, waiting
+    /// `backoff` before the first retry and doubling the wait before each
+    /// subsequent retry.
+    pub fn retry(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    /// Fail each attempt which takes longer than `timeout` to complete.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns true if this policy requires the runtime to have a
+    /// [`Clock`].
+    pub fn uses_clock(&self) -> bool {
+        self.timeout.is_some() || (self.retries > 0 && self.backoff > Duration::from_secs(0))
+    }
+
+    pub(crate) fn keeps_stale(&self) -> bool {
+        self.keep_stale
+    }
+
+    /// Run futures returned by `attempt` until one succeeds or the policy's
+    /// retries are exhausted. `clock` must be provided if the policy
+    /// [`LoadPolicy::uses_clock`].
+    pub(crate) async fn run<Fut, Output, Error>(
+        self,
+        clock: Option<Rc<dyn Clock>>,
+        mut attempt: impl FnMut() -> Fut,
+    ) -> Result<Output, LoadError<Error>>
+    where
+        Fut: Future<Output = Result<Output, Error>>,
+    {
+        let clock = || clock.as_ref().expect("clock presence checked before running policies");
+        let mut backoff = self.backoff;
+        let mut retries = self.retries;
+        loop {
+            let outcome = match self.timeout {
+                Some(timeout) => {
+                    let attempt = Box::pin(attempt());
+                    match select(attempt, clock().sleep(timeout)).await {
+                        Either::Left((outcome, _)) => outcome.map_err(LoadError::Failed),
+                        Either::Right(((), _)) => Err(LoadError::TimedOut),
+                    }
+                }
+                None => attempt().await.map_err(LoadError::Failed),
+            };
+
+            match outcome {
+                Err(_) if retries > 0 => {
+                    retries -= 1;
+                    if backoff > Duration::from_secs(0) {
+                        clock().sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+}
+
+/// The reason a future loaded with [`crate::load_with_policy`] failed, after
+/// any retries.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoadError<Error> {
+    /// The last attempt's future returned an error.
+    Failed(Error),
+    /// The last attempt took longer than the policy's timeout.
+    TimedOut,
+}
+
+impl<Error> Display for LoadError<Error>
+where
+    Error: Display,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            LoadError::Failed(e) => e.fmt(f),
+            LoadError::TimedOut => f.write_str("timed out"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_with_policy, runtime::RunLoop, testing::TestLoop};
+    use futures::{
+        executor::LocalPool,
+        future::{pending, ready},
+    };
+    use std::{cell::RefCell, task::Poll};
+
+    #[test]
+    fn retries_with_backoff() {
+        let attempts = Rc::new(RefCell::new(0));
+        let counted = attempts.clone();
+        let policy = LoadPolicy::new().retry(2, Duration::from_millis(10));
+        let mut test = TestLoop::new(move || {
+            let counted = counted.clone();
+            load_with_policy(
+                &policy,
+                &(),
+                move |()| {
+                    *counted.borrow_mut() += 1;
+                    ready(Err::<(), _>("nope"))
+                },
+                Clone::clone,
+            )
+        });
+
+        assert_eq!(test.run_once(), Poll::Pending);
+        test.run_tasks();
+        assert_eq!(*attempts.borrow(), 1);
+        assert_eq!(test.clock().next_deadline(), Some(Duration::from_millis(10)));
+
+        test.advance(Duration::from_millis(10));
+        assert_eq!(*attempts.borrow(), 2);
+        assert_eq!(
+            test.clock().next_deadline(),
+            Some(Duration::from_millis(30)),
+            "backoff doubles"
+        );
+
+        test.advance(Duration::from_millis(20));
+        assert_eq!(*attempts.borrow(), 3);
+        assert_eq!(test.clock().next_deadline(), None, "retries exhausted");
+        assert_eq!(test.run_once(), Poll::Ready(Err(LoadError::Failed("nope"))));
+    }
+
+    #[test]
+    fn times_out() {
+        let policy = LoadPolicy::new().timeout(Duration::from_secs(1));
+        let mut test = TestLoop::new(move || {
+            load_with_policy(&policy, &(), |()| pending::<Result<(), ()>>(), Clone::clone)
+        });
+
+        assert_eq!(test.run_once(), Poll::Pending);
+        test.run_tasks();
+        assert_eq!(test.run_once(), Poll::Pending);
+
+        assert_eq!(test.clock().next_deadline(), Some(Duration::from_secs(1)));
+        test.advance(Duration::from_secs(1));
+        assert_eq!(test.run_once(), Poll::Ready(Err(LoadError::TimedOut)));
+    }
+
+    #[test]
+    #[should_panic(expected = "set_clock")]
+    fn timing_requires_a_clock() {
+        let policy = LoadPolicy::new().timeout(Duration::from_secs(1));
+        let mut rt = RunLoop::new(move || {
+            load_with_policy(&policy, &(), |()| pending::<Result<(), ()>>(), Clone::clone)
+        });
+        rt.set_task_executor(LocalPool::new().spawner());
+        let _ = rt.run_once();
+    }
+}
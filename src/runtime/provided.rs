@@ -0,0 +1,85 @@
+use super::{reads::Observed, Reads};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::{Rc, Weak},
+};
+
+/// A value offered to a subtree by [`crate::provide`]. It's cached at the
+/// providing callsite so that consumers can tell whether it has changed since
+/// they last read it.
+pub(crate) struct Provided<T> {
+    inner: Rc<InnerProvided<T>>,
+}
+
+struct InnerProvided<T> {
+    value: RefCell<Rc<T>>,
+    generation: Cell<u64>,
+}
+
+impl<T: 'static> Provided<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Rc::new(InnerProvided {
+                value: RefCell::new(Rc::new(value)),
+                generation: Cell::new(0),
+            }),
+        }
+    }
+
+    /// Replace the provided value if it isn't equal to the current one,
+    /// invalidating the reads of the current one.
+    pub fn update(&self, value: T)
+    where
+        T: PartialEq,
+    {
+        if **self.inner.value.borrow() != value {
+            self.inner.value.replace(Rc::new(value));
+            self.inner.generation.set(self.inner.generation.get() + 1);
+        }
+    }
+
+    /// Returns the provided value, recording the read in the current
+    /// [`Reads`].
+    pub fn read(&self) -> Rc<T> {
+        Reads::record_provided(Rc::new(ProvidedRead {
+            provided: Rc::downgrade(&self.inner),
+            generation: self.inner.generation.get(),
+        }));
+        self.inner.value.borrow().clone()
+    }
+}
+
+impl<T> Clone for Provided<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Debug for Provided<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Provided")
+            .field("type", &std::any::type_name::<T>())
+            .field("generation", &self.inner.generation.get())
+            .finish()
+    }
+}
+
+struct ProvidedRead<T> {
+    provided: Weak<InnerProvided<T>>,
+    generation: u64,
+}
+
+impl<T> Observed for ProvidedRead<T> {
+    fn is_stale(&self) -> bool {
+        if let Some(provided) = self.provided.upgrade() {
+            provided.generation.get() != self.generation
+        } else {
+            true
+        }
+    }
+
+    fn is_provided(&self) -> bool {
+        true
+    }
+}
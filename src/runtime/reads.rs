@@ -0,0 +1,134 @@
+use super::Var;
+use crate::Commit;
+use illicit::AsContext;
+use parking_lot::Mutex;
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::{Arc, Weak},
+};
+
+/// Records the state variables which are rooted and the provided values which
+/// are consumed while running a closure, used to decide whether a
+/// [`crate::derived`] value needs to be recomputed.
+///
+/// Cached closures only record consumed values, see [`Reads::track_provided`].
+#[derive(Clone, Default)]
+pub(crate) struct Reads {
+    inner: Rc<RefCell<InnerReads>>,
+}
+
+/// A [`Reads`] which only records consumed values, offered while running the
+/// initializer of a cached value.
+#[derive(Clone, Debug)]
+struct ProvidedReads(Reads);
+
+#[derive(Default)]
+struct InnerReads {
+    generation: u64,
+    observed: Vec<Rc<dyn Observed>>,
+}
+
+impl Reads {
+    /// Record a read of `var` which observed `commit`, if a `Reads` is in the
+    /// current environment.
+    pub fn record<State: 'static>(var: &Arc<Mutex<Var<State>>>, commit: &Commit<State>) {
+        if let Ok(reads) = illicit::get::<Reads>() {
+            reads.push(Rc::new(VarRead { var: Arc::downgrade(var), commit: commit.clone() }));
+        }
+    }
+
+    /// Record a read of a provided value in the current `Reads` and
+    /// `ProvidedReads`, if any.
+    pub fn record_provided(observed: Rc<dyn Observed>) {
+        if let Ok(reads) = illicit::get::<Reads>() {
+            reads.push(observed.clone());
+        }
+        if let Ok(reads) = illicit::get::<ProvidedReads>() {
+            reads.0.push(observed);
+        }
+    }
+
+    /// Returns a number which changes every time one of the previously recorded
+    /// reads has been invalidated by a new commit.
+    pub fn generation(&self) -> u64 {
+        let mut inner = self.inner.borrow_mut();
+        if inner.observed.iter().any(|o| o.is_stale()) {
+            inner.generation += 1;
+            inner.observed.clear();
+        }
+        inner.generation
+    }
+
+    /// Run `op`, recording any state variables it roots.
+    pub fn track<R>(&self, op: impl FnOnce() -> R) -> R {
+        self.inner.borrow_mut().observed.clear();
+        self.clone().offer(op)
+    }
+
+    /// Run `op`, recording only the provided values it consumes.
+    pub fn track_provided<R>(&self, op: impl FnOnce() -> R) -> R {
+        self.inner.borrow_mut().observed.clear();
+        ProvidedReads(self.clone()).offer(op)
+    }
+
+    /// Copy all of our reads into the enclosing `Reads`, if any, so that an
+    /// outer derived value is invalidated along with an inner one. Reads of
+    /// provided values are also copied into the enclosing `ProvidedReads`.
+    pub fn forward(&self) {
+        let inner = self.inner.borrow();
+        if let Ok(outer) = illicit::get::<Reads>() {
+            for observed in &inner.observed {
+                outer.push(observed.clone());
+            }
+        }
+        if let Ok(outer) = illicit::get::<ProvidedReads>() {
+            for observed in inner.observed.iter().filter(|o| o.is_provided()) {
+                outer.0.push(observed.clone());
+            }
+        }
+    }
+
+    fn push(&self, observed: Rc<dyn Observed>) {
+        self.inner.borrow_mut().observed.push(observed);
+    }
+}
+
+impl Debug for Reads {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Reads")
+            .field("generation", &inner.generation)
+            .field("observed", &inner.observed.len())
+            .finish()
+    }
+}
+
+/// A single read which may be invalidated.
+pub(crate) trait Observed {
+    /// Returns true if the value observed is no longer the latest.
+    fn is_stale(&self) -> bool;
+
+    /// Returns true if the value observed was provided with [`crate::provide`].
+    fn is_provided(&self) -> bool;
+}
+
+struct VarRead<State> {
+    var: Weak<Mutex<Var<State>>>,
+    commit: Commit<State>,
+}
+
+impl<State> Observed for VarRead<State> {
+    fn is_stale(&self) -> bool {
+        if let Some(var) = self.var.upgrade() {
+            !std::ptr::eq(var.lock().latest(), &*self.commit)
+        } else {
+            true
+        }
+    }
+
+    fn is_provided(&self) -> bool {
+        false
+    }
+}
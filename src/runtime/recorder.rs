@@ -0,0 +1,227 @@
+use super::{transaction::TxnStatus, Revision, Var};
+use crate::Commit;
+use parking_lot::Mutex;
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::{Arc, Weak},
+};
+use topo::CallId;
+
+/// A single commit to a state variable, recorded by a [`super::Runtime`] after
+/// a call to [`super::Runtime::record_commits`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommitRecord {
+    id: CallId,
+    revision: Revision,
+    type_name: &'static str,
+    value: String,
+}
+
+impl CommitRecord {
+    /// The callsite of the state variable which received the commit.
+    pub fn id(&self) -> CallId {
+        self.id
+    }
+
+    /// The revision during or after which the commit was made. The committed
+    /// value is first visible in the following revision.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// The name of the state variable's type.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The `Debug` representation of the committed value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Tracks the state variables of a runtime whose commits are recorded, and
+/// steps them back and forth between revisions.
+#[derive(Clone, Default)]
+pub(crate) struct Recorder {
+    inner: Rc<RefCell<InnerRecorder>>,
+    log: CommitLog,
+}
+
+#[derive(Default)]
+struct InnerRecorder {
+    describers: HashMap<TypeId, Box<dyn Any>>,
+    vars: Vec<Box<dyn Travel>>,
+}
+
+impl Recorder {
+    /// Record commits to state variables of type `T`.
+    pub fn register<T: Debug + 'static>(&self) {
+        let describe: fn(&T) -> String = |value| format!("{:?}", value);
+        self.inner.borrow_mut().describers.insert(TypeId::of::<T>(), Box::new(describe));
+    }
+
+    /// Record future commits to `var` if its type was registered.
+    pub fn track<T: 'static>(&self, var: &Arc<Mutex<Var<T>>>) {
+        let mut inner = self.inner.borrow_mut();
+        let describe = inner.describers.get(&TypeId::of::<T>());
+        if let Some(&describe) = describe.and_then(|d| d.downcast_ref::<fn(&T) -> String>()) {
+            var.lock().start_recording(|initial| History {
+                log: self.log.clone(),
+                type_name: type_name::<T>(),
+                describe,
+                initial: initial.clone(),
+                commits: Vec::new(),
+            });
+            inner.vars.push(Box::new(Arc::downgrade(var)));
+        }
+    }
+
+    /// Mark the start of a new revision, which subsequent commits are recorded
+    /// against.
+    pub fn set_revision(&self, revision: Revision) {
+        self.log.inner.lock().revision = revision;
+    }
+
+    /// Returns all commits recorded so far, in the order they were made,
+    /// except those from unfinished or aborted transactions.
+    pub fn commits(&self) -> Vec<CommitRecord> {
+        let log = self.log.inner.lock();
+        let visible = log.commits.iter().filter(|(_, txn)| TxnStatus::is_visible(txn.as_ref()));
+        visible.map(|(record, _)| record.clone()).collect()
+    }
+
+    /// Enqueue a commit to each recorded state variable which restores the value
+    /// it had at the start of `revision`.
+    pub fn travel_to(&self, revision: Revision) {
+        self.inner.borrow_mut().vars.retain(|var| var.travel_to(revision));
+    }
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Recorder")
+            .field("describers", &inner.describers.len())
+            .field("vars", &inner.vars.len())
+            .field("log", &self.log)
+            .finish()
+    }
+}
+
+/// The commits recorded by a runtime. Shared with state variables, which may
+/// receive commits from other threads.
+#[derive(Clone, Debug, Default)]
+struct CommitLog {
+    inner: Arc<Mutex<InnerLog>>,
+}
+
+#[derive(Debug, Default)]
+struct InnerLog {
+    revision: Revision,
+    commits: Vec<(CommitRecord, Option<TxnStatus>)>,
+}
+
+/// The recorded commits of a single state variable.
+pub(crate) struct History<State> {
+    log: CommitLog,
+    type_name: &'static str,
+    describe: fn(&State) -> String,
+    initial: Commit<State>,
+    commits: Vec<(Revision, Commit<State>, Option<TxnStatus>)>,
+}
+
+impl<State> History<State> {
+    /// Add `commit` to this variable's history and to the runtime's log. If it
+    /// was made in the transaction with status `txn`, it's ignored if the
+    /// transaction aborts.
+    pub fn record(&mut self, commit: &Commit<State>, txn: Option<&TxnStatus>) {
+        let mut log = self.log.inner.lock();
+        let revision = log.revision;
+        log.commits.retain(|(_, txn)| !TxnStatus::is_discarded(txn.as_ref()));
+        let record = CommitRecord {
+            id: commit.id,
+            revision,
+            type_name: self.type_name,
+            value: (self.describe)(commit),
+        };
+        log.commits.push((record, txn.cloned()));
+
+        self.commits.retain(|(_, _, txn)| !TxnStatus::is_discarded(txn.as_ref()));
+        self.commits.push((revision, commit.clone(), txn.cloned()));
+    }
+
+    /// Returns the last commit made before `revision`, or the variable's
+    /// initial value if there wasn't one.
+    pub fn as_of(&self, revision: Revision) -> Commit<State> {
+        let before = self.commits.iter().take_while(|(made, ..)| *made < revision);
+        let visible = before.filter(|(.., txn)| TxnStatus::is_visible(txn.as_ref()));
+        visible.last().map_or(&self.initial, |(_, commit, _)| commit).clone()
+    }
+}
+
+/// A recorded state variable which may have been dropped.
+trait Travel {
+    /// Restore the value as of `revision`, returning false if the variable
+    /// has been dropped.
+    fn travel_to(&self, revision: Revision) -> bool;
+}
+
+impl<State: 'static> Travel for Weak<Mutex<Var<State>>> {
+    fn travel_to(&self, revision: Revision) -> bool {
+        if let Some(var) = self.upgrade() {
+            var.lock().travel_to(revision);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{runtime::RunLoop, state};
+
+    #[test]
+    fn only_registered_types_are_recorded() {
+        let mut rt = RunLoop::new(|| (state(|| 0u8).1, state(|| 'a').1));
+        rt.record_commits::<u8>();
+
+        let (number, letter) = rt.run_once();
+        number.set(1);
+        number.set(2);
+        letter.set('b');
+        rt.run_once();
+
+        let log = rt.commit_log();
+        let values = log.iter().map(|c| c.value()).collect::<Vec<_>>();
+        assert_eq!(values, ["1", "2"], "every commit is recorded, even those overwritten");
+        assert!(log.iter().all(|c| c.type_name() == "u8" && c.id() == log[0].id()));
+    }
+
+    #[test]
+    fn travel_back_and_forth() {
+        let mut rt = RunLoop::new(|| state(|| 0u8));
+        rt.record_commits::<u8>();
+
+        for i in 1..=3 {
+            let (current, key) = rt.run_once();
+            assert_eq!(*current, i - 1);
+            key.set(i);
+        }
+        let log = rt.commit_log();
+        assert_eq!(log.iter().map(|c| c.revision().0).collect::<Vec<_>>(), [1, 2, 3]);
+
+        rt.travel_to(crate::runtime::Revision(2));
+        assert_eq!(*rt.run_once().0, 1);
+        rt.travel_to(crate::runtime::Revision(1));
+        assert_eq!(*rt.run_once().0, 0);
+        rt.travel_to(crate::runtime::Revision(4));
+        assert_eq!(*rt.run_once().0, 3);
+        assert_eq!(rt.commit_log(), log, "traveling doesn't record commits");
+    }
+}
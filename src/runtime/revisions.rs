@@ -0,0 +1,112 @@
+use super::{pending::PendingCommits, Revision, RevisionStats, StatsCounters};
+use dyn_cache::{local::SharedLocalCache, sync::SharedSendCache, NamespaceStats};
+use futures::task::noop_waker;
+use std::task::Waker;
+
+/// The parts of a runtime which don't depend on whether it can be moved
+/// between threads: its revision, its cache, the commits made since the
+/// revision started, and the counters reported by its stats.
+///
+/// Both [`super::Runtime`] and [`super::SendRuntime`] run their revisions
+/// through this, so that they track revisions, collect their caches, and count
+/// their work in the same way.
+#[derive(Debug)]
+pub(crate) struct Revisions<Cache> {
+    pub revision: Revision,
+    pub cache: Cache,
+    pub pending_commits: PendingCommits,
+    pub stats: StatsCounters,
+    cache_stats: Vec<NamespaceStats>,
+    pub waker: Waker,
+}
+
+impl<Cache: RevisionCache> Default for Revisions<Cache> {
+    fn default() -> Self {
+        let stats = StatsCounters::default();
+        Self {
+            revision: Revision(0),
+            cache: Cache::default(),
+            pending_commits: PendingCommits::default(),
+            waker: stats.counting_waker(noop_waker()),
+            stats,
+            cache_stats: Vec::new(),
+        }
+    }
+}
+
+impl<Cache: RevisionCache> Revisions<Cache> {
+    /// Starts a new revision, forgetting the commits and counts of the last
+    /// one, and returns it.
+    pub fn begin(&mut self) -> Revision {
+        self.revision.0 += 1;
+        self.pending_commits.clear();
+        self.stats.reset();
+        self.revision
+    }
+
+    /// Runs `op` and then garbage collects the cache. If `op` panics the marks
+    /// it made on cached values are discarded, so that the next revision
+    /// collects them as though this one hadn't used them.
+    pub fn run_and_gc<Out>(&self, op: impl FnOnce() -> Out) -> Out {
+        let _rollback = scopeguard::guard_on_unwind(self.cache.clone(), |cache| cache.rollback());
+        let ret = op();
+        self.cache.gc();
+        ret
+    }
+
+    /// Finishes the current revision, recording the use of the cache since it
+    /// began.
+    pub fn finish(&mut self) {
+        self.cache_stats = self.cache.take_stats();
+    }
+
+    /// Sets the waker called when state variables receive commits.
+    pub fn set_state_change_waker(&mut self, wk: Waker) {
+        self.waker = self.stats.counting_waker(wk);
+    }
+
+    /// Returns counts of the work done during the latest revision.
+    pub fn stats(&self) -> RevisionStats {
+        self.stats.snapshot(self.revision, self.cache_stats.clone(), self.pending_commits.len())
+    }
+}
+
+/// The operations on a runtime's cache which are needed to run revisions.
+pub(crate) trait RevisionCache: Clone + Default {
+    /// Drops the values which weren't used since the last collection.
+    fn gc(&self);
+
+    /// Discards the marks made since the last collection.
+    fn rollback(&self);
+
+    /// Returns the use of the cache since this was last called.
+    fn take_stats(&self) -> Vec<NamespaceStats>;
+}
+
+impl RevisionCache for SharedLocalCache {
+    fn gc(&self) {
+        SharedLocalCache::gc(self)
+    }
+
+    fn rollback(&self) {
+        SharedLocalCache::rollback(self)
+    }
+
+    fn take_stats(&self) -> Vec<NamespaceStats> {
+        SharedLocalCache::take_stats(self)
+    }
+}
+
+impl RevisionCache for SharedSendCache {
+    fn gc(&self) {
+        SharedSendCache::gc(self)
+    }
+
+    fn rollback(&self) {
+        SharedSendCache::rollback(self)
+    }
+
+    fn take_stats(&self) -> Vec<NamespaceStats> {
+        SharedSendCache::take_stats(self)
+    }
+}
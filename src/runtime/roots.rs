@@ -0,0 +1,143 @@
+use super::Revision;
+use dyn_cache::local::SharedLocalCache;
+use std::{cell::RefCell, rc::Rc};
+
+/// The named roots hosted by a [`super::Runtime`], each of which has its own
+/// call tree in the runtime's cache.
+///
+/// The values cached by each root are anchored to a cache entry for that root.
+/// When the root runs, the anchor is re-initialized and the values it caches
+/// are retained only if they're used. When it doesn't run, the anchor is only
+/// read, so the values cached during its last run inherit its liveness.
+#[derive(Clone, Default)]
+pub(crate) struct Roots {
+    inner: Vec<NamedRoot>,
+}
+
+#[derive(Clone)]
+struct NamedRoot {
+    name: Rc<str>,
+    op: Rc<RefCell<dyn FnMut()>>,
+    ran_at: Revision,
+}
+
+/// The cache key of a root's anchor.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct RootAnchor(Rc<str>);
+
+impl Roots {
+    /// Adds a root named `name`, replacing the function of any existing root
+    /// with the same name.
+    pub fn insert(&mut self, name: &str, op: impl FnMut() + 'static) {
+        let op = Rc::new(RefCell::new(op));
+        if let Some(existing) = self.inner.iter_mut().find(|r| &*r.name == name) {
+            existing.op = op;
+        } else {
+            self.inner.push(NamedRoot { name: name.into(), op, ran_at: Revision(0) });
+        }
+    }
+
+    /// Removes the root named `name`, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.inner.len();
+        self.inner.retain(|r| &*r.name != name);
+        before != self.inner.len()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.inner.iter().any(|r| &*r.name == name)
+    }
+
+    /// Runs each root for which `should_run` returns true in its own call
+    /// tree, in the order they were added, and keeps the cached values of the
+    /// others alive. Must be called during a revision.
+    pub fn run(&self, cache: &SharedLocalCache, should_run: &dyn Fn(&str) -> bool) {
+        for root in &self.inner {
+            let anchor = RootAnchor(root.name.clone());
+            if should_run(&root.name) {
+                cache.hold(&anchor, &Revision::current(), |_| {
+                    topo::root(|| topo::call_in_slot(&*root.name, || (root.op.borrow_mut())()))
+                });
+            } else {
+                cache.hold(&anchor, &root.ran_at, |_| ());
+            }
+        }
+    }
+
+    /// Records that the roots for which `should_run` returns true ran during
+    /// `revision`.
+    pub fn mark_ran(&mut self, revision: Revision, should_run: &dyn Fn(&str) -> bool) {
+        for root in self.inner.iter_mut().filter(|r| should_run(&r.name)) {
+            root.ran_at = revision;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cache, runtime::Runtime, state};
+    use std::{cell::Cell, rc::Rc};
+
+    struct DropCounter(Rc<Cell<u32>>);
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn roots_which_dont_run_keep_their_values() {
+        let mut rt = Runtime::new();
+        let (main_runs, main_drops) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let panel_drops = Rc::new(Cell::new(0));
+        let panel_count = Rc::new(Cell::new(0));
+
+        let (runs, drops) = (main_runs.clone(), main_drops.clone());
+        rt.add_root("main", move || {
+            runs.set(runs.get() + 1);
+            let drops = drops.clone();
+            cache(&(), |_| Rc::new(DropCounter(drops)));
+        });
+        let (drops, count) = (panel_drops.clone(), panel_count.clone());
+        rt.add_root("panel", move || {
+            let drops = drops.clone();
+            cache(&(), |_| Rc::new(DropCounter(drops)));
+            let (current, key) = state(|| 0);
+            count.set(*current);
+            key.set(*current + 1);
+        });
+
+        rt.run_roots();
+        assert_eq!((main_runs.get(), panel_count.get()), (1, 0));
+
+        for _ in 0..3 {
+            rt.run_root("main");
+        }
+        assert_eq!(main_runs.get(), 4);
+        assert_eq!((main_drops.get(), panel_drops.get()), (0, 0), "nothing was collected");
+
+        rt.run_root("panel");
+        assert_eq!(panel_count.get(), 1, "state survived revisions where the root didn't run");
+
+        assert!(rt.remove_root("panel"));
+        rt.run_root("main");
+        assert_eq!((main_drops.get(), panel_drops.get()), (0, 1));
+    }
+
+    #[test]
+    fn roots_have_separate_call_trees() {
+        let mut rt = Runtime::new();
+        let ids = Rc::new(std::cell::RefCell::new(Vec::new()));
+        for name in &["first", "second"] {
+            let ids = ids.clone();
+            rt.add_root(name, move || ids.borrow_mut().push(topo::CallId::current()));
+        }
+
+        rt.run_roots();
+        rt.run_root("second");
+        rt.run_root("first");
+        let ids = ids.borrow();
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!((ids[0], ids[1]), (ids[3], ids[2]));
+    }
+}
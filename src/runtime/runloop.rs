@@ -1,42 +1,59 @@
-use super::{Revision, Runtime};
+use super::{Clock, Priority, Revision, Runtime, SendRuntime, Unstable};
 use futures::{
     stream::{Stream, StreamExt},
-    task::LocalSpawn,
+    task::{LocalSpawn, Spawn},
 };
 use std::{
     pin::Pin,
     task::{Context as FutContext, Poll, Waker},
 };
 
-/// A [`Runtime`] that is bound with a particular root function.
-///
-/// If running in a context with an async executor, can be consumed as a
-/// [`futures::Stream`] of [`crate::runtime::Revision`]s in order to provide
-/// the [`super::Runtime`] with a [`std::task::Waker`].
-pub struct RunLoop<Root> {
-    inner: Runtime,
+macro_rules! doc_comment {
+    ($($contents:expr)+ => $($item:tt)+) => {
+        doc_comment! {@ concat!($($contents),+), $($item)+ }
+    };
+    (@ $contents:expr, $($item:tt)+) => {
+        #[doc = $contents]
+        $($item)+
+    };
+}
+
+/// Defines a loop type binding `$runtime` with a root function, so that the
+/// loops of every kind of runtime share the same implementation.
+macro_rules! define_run_loop {
+    (
+        $(#[$meta:meta])*
+        $name:ident($runtime:ident);
+        $(#[$run_until_stable:meta])*
+    ) => {
+$(#[$meta])*
+pub struct $name<Root> {
+    inner: $runtime,
     root: Root,
 }
 
-impl super::Runtime {
+impl $runtime {
     /// Returns this runtime bound with a specific root function it will run in
     /// a loop.
-    pub fn looped<Root, Out>(self, root: Root) -> RunLoop<Root>
+    pub fn looped<Root, Out>(self, root: Root) -> $name<Root>
     where
         Root: FnMut() -> Out,
     {
-        RunLoop { inner: self, root }
+        $name { inner: self, root }
     }
 }
 
-impl<Root, Out> RunLoop<Root>
+impl<Root, Out> $name<Root>
 where
     Root: FnMut() -> Out + Unpin,
 {
-    /// Creates a new `Runtime` attached to the provided root function.
-    pub fn new(root: Root) -> RunLoop<Root> {
-        RunLoop { root, inner: Runtime::new() }
+doc_comment! {"
+Creates a new [`" stringify!($runtime) "`] attached to the provided root function.
+"=>
+    pub fn new(root: Root) -> $name<Root> {
+        $name { root, inner: $runtime::new() }
     }
+}
 
     /// Returns the runtime's current Revision.
     pub fn revision(&self) -> Revision {
@@ -49,10 +66,14 @@ where
         self.inner.set_state_change_waker(wk);
     }
 
-    /// Sets the executor that will be used to spawn normal priority tasks.
-    pub fn set_task_executor(&mut self, sp: impl LocalSpawn + 'static) {
-        self.inner.set_task_executor(sp);
+doc_comment! {"
+Returns counts of the work done during the latest revision. See
+[`" stringify!($runtime) "::stats`].
+"=>
+    pub fn stats(&self) -> super::RevisionStats {
+        self.inner.stats()
     }
+}
 
     /// Run the root function once within this runtime's context, returning the
     /// result.
@@ -60,6 +81,22 @@ where
         self.inner.run_once(&mut self.root)
     }
 
+    $(#[$run_until_stable])*
+    pub fn run_until_stable(
+        &mut self,
+        max_revisions: u64,
+        mut run_tasks: impl FnMut(),
+    ) -> Result<Out, Unstable> {
+        for _ in 0..max_revisions {
+            let out = self.run_once();
+            run_tasks();
+            if self.inner.pending_commits().is_empty() {
+                return Ok(out);
+            }
+        }
+        Err(Unstable::new(max_revisions, self.inner.pending_commits().callsites()))
+    }
+
     /// Poll this runtime without exiting. Discards any value returned from the
     /// root function. The future yields in between revisions and is woken on
     /// state changes.
@@ -70,12 +107,12 @@ where
     }
 
     /// Unbinds the runtime from its current root function, returning both.
-    pub fn unloop(self) -> (Runtime, Root) {
+    pub fn unloop(self) -> ($runtime, Root) {
         (self.inner, self.root)
     }
 }
 
-impl<Root, Out> Stream for RunLoop<Root>
+impl<Root, Out> Stream for $name<Root>
 where
     Root: FnMut() -> Out + Unpin,
 {
@@ -87,6 +124,149 @@ where
         let this = self.get_mut();
         this.inner.set_state_change_waker(cx.waker().clone());
         let out = this.run_once();
-        Poll::Ready(Some((this.inner.revision, out)))
+        Poll::Ready(Some((this.inner.revision(), out)))
+    }
+}
+    };
+}
+
+define_run_loop! {
+    /// A [`Runtime`] that is bound with a particular root function.
+    ///
+    /// If running in a context with an async executor, can be consumed as a
+    /// [`futures::Stream`] of [`crate::runtime::Revision`]s in order to provide
+    /// the [`super::Runtime`] with a [`std::task::Waker`].
+    RunLoop(Runtime);
+    /// Run revisions until one completes without any state variables receiving
+    /// commits, returning the output of the last revision. Commits made by
+    /// effects after a revision count towards that revision.
+    ///
+    /// `run_tasks` is called after each revision to drive the runtime's
+    /// executor, for example by running a `LocalPool` until it stalls, so that
+    /// commits made by the tasks it runs count towards that revision too. Loads
+    /// which can finish while it runs are ready by the time this returns.
+    ///
+    /// # Errors
+    ///
+    /// If state variables are still receiving commits after `max_revisions`
+    /// revisions, returns an error naming the callsites of the variables which
+    /// received commits during the last revision.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::{executor::LocalPool, future::ready};
+    /// use moxie::{load, runtime::RunLoop, state};
+    /// use std::task::Poll;
+    ///
+    /// let mut rt = RunLoop::new(|| {
+    ///     let (count, set_count) = state(|| 0);
+    ///     if *count < 3 {
+    ///         set_count.set(*count + 1);
+    ///     }
+    ///     *count
+    /// });
+    /// assert_eq!(rt.run_until_stable(10, || ()), Ok(3));
+    /// assert_eq!(rt.revision().0, 4);
+    ///
+    /// let mut rt = RunLoop::new(|| load(&(), |()| ready("loaded")));
+    /// let mut pool = LocalPool::new();
+    /// rt.set_task_executor(pool.spawner());
+    /// assert_eq!(rt.run_until_stable(10, || pool.run_until_stalled()), Ok(Poll::Ready("loaded")));
+    ///
+    /// let mut rt = RunLoop::new(|| {
+    ///     let (count, set_count) = state(|| 0);
+    ///     set_count.set(*count + 1);
+    ///     set_count.id()
+    /// });
+    /// let looping = rt.run_once();
+    /// let unstable = rt.run_until_stable(10, || ()).unwrap_err();
+    /// assert_eq!(unstable.committing(), [looping]);
+    /// ```
+}
+
+impl<Root, Out> RunLoop<Root>
+where
+    Root: FnMut() -> Out + Unpin,
+{
+    /// Sets the executor that will be used to spawn normal priority tasks.
+    pub fn set_task_executor(&mut self, sp: impl LocalSpawn + 'static) {
+        self.inner.set_task_executor(sp);
+    }
+
+    /// Sets the executor that will be used to spawn tasks of the given
+    /// `priority`. See [`Runtime::set_priority_executor`].
+    pub fn set_priority_executor(&mut self, priority: Priority, sp: impl LocalSpawn + 'static) {
+        self.inner.set_priority_executor(priority, sp);
+    }
+
+    /// Sets the [`Clock`] used for timers. See [`Runtime::set_clock`].
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.inner.set_clock(clock);
+    }
+
+    /// Includes state variables of type `T` in snapshots. See
+    /// [`Runtime::persist_state`].
+    #[cfg(feature = "serde")]
+    pub fn persist_state<T>(&mut self)
+    where
+        T: serde_crate::de::DeserializeOwned + serde_crate::Serialize + 'static,
+    {
+        self.inner.persist_state::<T>();
+    }
+
+    /// Returns the latest values of persisted state variables. See
+    /// [`Runtime::snapshot_state`].
+    #[cfg(feature = "serde")]
+    pub fn snapshot_state(&self) -> super::StateSnapshot {
+        self.inner.snapshot_state()
+    }
+
+    /// Seeds state variables from a snapshot. See [`Runtime::restore_state`].
+    #[cfg(feature = "serde")]
+    pub fn restore_state(&mut self, snapshot: super::StateSnapshot) {
+        self.inner.restore_state(snapshot);
+    }
+
+    /// Records commits to state variables of type `T`. See
+    /// [`Runtime::record_commits`].
+    pub fn record_commits<T: std::fmt::Debug + 'static>(&mut self) {
+        self.inner.record_commits::<T>();
+    }
+
+    /// Returns all recorded commits. See [`Runtime::commit_log`].
+    pub fn commit_log(&self) -> Vec<super::CommitRecord> {
+        self.inner.commit_log()
+    }
+
+    /// Restores recorded state variables to their values at the start of
+    /// `revision`. See [`Runtime::travel_to`].
+    pub fn travel_to(&mut self, revision: Revision) {
+        self.inner.travel_to(revision);
+    }
+
+    /// Returns the callsites of state variables which received commits since
+    /// the start of the latest revision.
+    pub(crate) fn committing(&self) -> Vec<topo::CallId> {
+        self.inner.pending_commits().callsites()
+    }
+}
+
+define_run_loop! {
+    /// A [`SendRuntime`] that is bound with a particular root function. The
+    /// counterpart of [`RunLoop`] which can be moved between threads.
+    SendRunLoop(SendRuntime);
+    /// Run revisions until one completes without any state variables receiving
+    /// commits, calling `run_tasks` after each to drive the runtime's executor.
+    /// See [`RunLoop::run_until_stable`].
+}
+
+impl<Root, Out> SendRunLoop<Root>
+where
+    Root: FnMut() -> Out + Unpin,
+{
+    /// Sets the executor that will be used to spawn tasks.
+    pub fn set_task_executor(&mut self, sp: impl Spawn + Send + Sync + 'static) {
+        self.inner.set_task_executor(sp);
     }
 }
@@ -0,0 +1,283 @@
+use super::{
+    pending::PendingCommits, CachedVar, JunkSpawner, Revision, RevisionStats, Revisions,
+    StatsCounters, Var,
+};
+use crate::{Commit, Key};
+use dyn_cache::sync::SharedSendCache;
+use futures::{future::abortable, task::Spawn};
+use illicit::AsContext;
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Poll, Waker},
+};
+
+/// A [`super::Runtime`] which can be moved between threads. It stores its cache
+/// in a [`dyn_cache::sync::SharedSendCache`] and spawns tasks onto a
+/// [`futures::task::Spawn`] executor.
+///
+/// Code running in a `SendRuntime` uses the functions in [`crate::sync`]
+/// rather than their counterparts in the crate root, which require a
+/// [`super::Runtime`]. Revisions, garbage collection, and [`SendRuntime::stats`]
+/// behave as they do in a [`super::Runtime`], but only values which are `Send`
+/// can be stored, and [`crate::effect`] and [`crate::derived`] are not
+/// supported.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRuntime, sync::state};
+///
+/// fn root() -> u8 {
+///     let (count, key) = state(|| 0u8);
+///     key.set(*count + 1);
+///     *count
+/// }
+///
+/// let mut rt = SendRuntime::new();
+/// assert_eq!(rt.run_once(root), 0);
+///
+/// let count = std::thread::spawn(move || rt.run_once(root)).join().unwrap();
+/// assert_eq!(count, 1);
+/// ```
+pub struct SendRuntime {
+    revisions: Revisions<SharedSendCache>,
+    spawner: SendSpawner,
+}
+
+impl Default for SendRuntime {
+    fn default() -> SendRuntime {
+        SendRuntime::new()
+    }
+}
+
+impl SendRuntime {
+    /// Construct a new [`SendRuntime`] with blank storage and no external waker
+    /// or task executor.
+    pub fn new() -> Self {
+        Self { revisions: Revisions::default(), spawner: SendSpawner(Arc::new(JunkSpawner)) }
+    }
+
+    /// The current revision of the runtime, or how many times `run_once` has
+    /// been invoked.
+    pub fn revision(&self) -> Revision {
+        self.revisions.revision
+    }
+
+    /// Runs the root closure once with access to the runtime context,
+    /// increments the runtime's `Revision`, and drops any cached values
+    /// which were not marked alive. Recovers from panics in the root closure
+    /// like [`super::Runtime::run_once`].
+    pub fn run_once<Out>(&mut self, op: impl FnOnce() -> Out) -> Out {
+        let _revision = self.revisions.begin();
+        #[cfg(feature = "tracing-spans")]
+        let _span = tracing::debug_span!("run_once", revision = _revision.0).entered();
+        let ret = self.revisions.run_and_gc(|| self.context_handle().offer(|| topo::call(op)));
+        self.revisions.finish();
+        ret
+    }
+
+    /// Sets the [`std::task::Waker`] which will be called when state variables
+    /// receive commits.
+    pub fn set_state_change_waker(&mut self, wk: Waker) {
+        self.revisions.set_state_change_waker(wk);
+    }
+
+    /// Sets the executor that will be used to spawn tasks.
+    pub fn set_task_executor(&mut self, sp: impl Spawn + Send + Sync + 'static) {
+        self.spawner = SendSpawner(Arc::new(sp));
+    }
+
+    /// Returns counts of the work done during the latest revision. See
+    /// [`super::Runtime::stats`].
+    pub fn stats(&self) -> RevisionStats {
+        self.revisions.stats()
+    }
+
+    pub(crate) fn pending_commits(&self) -> &PendingCommits {
+        &self.revisions.pending_commits
+    }
+
+    fn context_handle(&self) -> SendContext {
+        let revisions = &self.revisions;
+        SendContext {
+            revision: revisions.revision,
+            spawner: self.spawner.clone(),
+            cache: revisions.cache.clone(),
+            pending_commits: revisions.pending_commits.clone(),
+            stats: revisions.stats.clone(),
+            waker: revisions.waker.clone(),
+        }
+    }
+}
+
+impl Debug for SendRuntime {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("SendRuntime")
+            .field("revision", &self.revisions.revision)
+            .field("cache", &self.revisions.cache)
+            .field("spawner", &self.spawner)
+            .finish()
+    }
+}
+
+/// A handle to the current [`SendRuntime`] which is offered via [`illicit`]
+/// contexts, the thread-safe counterpart of `Context`.
+#[derive(Debug)]
+pub(crate) struct SendContext {
+    revision: Revision,
+    pub cache: SharedSendCache,
+    spawner: SendSpawner,
+    pending_commits: PendingCommits,
+    stats: StatsCounters,
+    waker: Waker,
+}
+
+impl SendContext {
+    /// Returns the revision for which this context was created.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Load a [`crate::runtime::Var`] with the provided argument and
+    /// initializer. Re-initializes the `Var` whenever `arg` changes.
+    pub fn cache_state<Arg, Input, Output>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+    ) -> (Commit<Output>, Key<Output>)
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + Send + 'static,
+        Output: Send + Sync + 'static,
+    {
+        let var = self.cache.cache_with(
+            id,
+            arg,
+            |arg| {
+                let id = topo::CallId::current();
+                CachedVar(Var::new(id, self.waker.clone(), self.pending_commits.clone(), init(arg)))
+            },
+            |cached| cached.0.clone(),
+        );
+        Var::root(var)
+    }
+
+    /// Load a value from the future returned by `init` whenever `capture`
+    /// changes, returning the result of calling `with` with the loaded
+    /// value. Cancels the running future if there's no longer interest
+    /// in its output.
+    ///
+    /// # Panics
+    ///
+    /// If the [`SendRuntime`] from which `self` was created did not have
+    /// a valid call to `set_task_executor`.
+    pub fn load_with<Arg, Input, Fut, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Fut,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Poll<Ret>
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + Send + 'static,
+        Fut: Future<Output = Output> + Send + 'static,
+        Output: Send + Sync + 'static,
+        Ret: 'static,
+    {
+        let (_, set_result): (_, Key<Poll<Output>>) = self.cache_state(id, &(), |()| Poll::Pending);
+        let mut set_result2 = set_result.clone();
+        self.cache.hold(id, arg, |arg| {
+            // before we spawn the new task we need to mark it pending
+            set_result.force(Poll::Pending);
+
+            let (fut, aborter) = abortable(init(arg));
+            let finished = Arc::new(AtomicBool::new(false));
+            let set_finished = finished.clone();
+            let task = async move {
+                if let Ok(to_store) = fut.await {
+                    set_result.update(|_| Some(Poll::Ready(to_store)));
+                }
+                set_finished.store(true, Ordering::Release);
+            };
+            self.spawner
+                .0
+                .spawn_obj(Box::pin(task).into())
+                .expect("that set_task_executor has been called");
+            self.stats.load_spawned();
+
+            let stats = self.stats.clone();
+            scopeguard::guard(aborter, move |a| {
+                if !finished.load(Ordering::Acquire) {
+                    stats.load_aborted();
+                }
+                a.abort()
+            })
+        });
+
+        set_result2.refresh();
+
+        match &*set_result2 {
+            Poll::Ready(ref stored) => Poll::Ready(with(stored)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SendSpawner(Arc<dyn Spawn + Send + Sync>);
+
+impl Debug for SendSpawner {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_fmt(format_args!("{:p}", &self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::SendRunLoop;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn runtimes_are_send() {
+        let rt = SendRuntime::new();
+        assert_send(&rt);
+        assert_send(&rt.looped(|| crate::sync::state(|| 0u8)));
+    }
+
+    #[test]
+    fn loads_on_a_thread_pool() {
+        let pool = futures::executor::ThreadPool::new().unwrap();
+        let (send, recv) = futures::channel::oneshot::channel();
+        let mut recv = Some(recv);
+        let mut rt = SendRunLoop::new(move || {
+            crate::sync::load_once(|| {
+                let recv = recv.take().unwrap();
+                async move { recv.await.unwrap() }
+            })
+        });
+        rt.set_task_executor(pool);
+
+        assert_eq!(rt.run_once(), Poll::Pending);
+        assert_eq!(rt.stats().loads_spawned(), 1);
+        send.send(7u8).unwrap();
+        let mut rt = std::thread::spawn(move || {
+            while rt.run_once().is_pending() {
+                std::thread::yield_now();
+            }
+            rt
+        })
+        .join()
+        .unwrap();
+        assert_eq!(rt.run_once(), Poll::Ready(7));
+    }
+}
@@ -0,0 +1,185 @@
+use super::Revision;
+use dyn_cache::NamespaceStats;
+use futures::task::{waker, ArcWake};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::Waker,
+};
+
+/// Counts of the work a [`super::Runtime`] did during one revision, returned by
+/// [`super::Runtime::stats`].
+///
+/// Commits and wakes are counted from the start of the revision until the
+/// start of the next one, so they include those made by event handlers and
+/// tasks after the revision's root function returned.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RevisionStats {
+    revision: Revision,
+    cache: Vec<NamespaceStats>,
+    commits: u64,
+    wakes: u64,
+    loads_spawned: u64,
+    loads_aborted: u64,
+}
+
+impl RevisionStats {
+    /// The revision which was counted.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Cache hits, misses, and garbage collected values for each type of
+    /// cached value which was used or collected during the revision.
+    pub fn cache(&self) -> &[NamespaceStats] {
+        &self.cache
+    }
+
+    /// The number of cached values returned without re-running their
+    /// initializers, across all namespaces.
+    pub fn cache_hits(&self) -> u64 {
+        self.cache.iter().map(NamespaceStats::hits).sum()
+    }
+
+    /// The number of cached values which were initialized, across all
+    /// namespaces.
+    pub fn cache_misses(&self) -> u64 {
+        self.cache.iter().map(NamespaceStats::misses).sum()
+    }
+
+    /// The number of cached values dropped by garbage collection at the end of
+    /// the revision, across all namespaces.
+    pub fn evictions(&self) -> u64 {
+        self.cache.iter().map(NamespaceStats::evictions).sum()
+    }
+
+    /// The number of commits made to state variables.
+    pub fn commits(&self) -> u64 {
+        self.commits
+    }
+
+    /// The number of times the runtime's state change waker was woken.
+    pub fn wakes(&self) -> u64 {
+        self.wakes
+    }
+
+    /// The number of tasks spawned by [`crate::load_with`] and related
+    /// functions.
+    pub fn loads_spawned(&self) -> u64 {
+        self.loads_spawned
+    }
+
+    /// The number of load tasks cancelled before completing, either because
+    /// their argument changed or because they were no longer called.
+    pub fn loads_aborted(&self) -> u64 {
+        self.loads_aborted
+    }
+}
+
+/// Counters for the current revision which are shared with wakers and tasks,
+/// which may be used from other threads.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StatsCounters {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    wakes: AtomicU64,
+    loads_spawned: AtomicU64,
+    loads_aborted: AtomicU64,
+}
+
+impl StatsCounters {
+    /// Zero all counters, called at the start of each revision.
+    pub fn reset(&self) {
+        self.inner.wakes.store(0, Ordering::Relaxed);
+        self.inner.loads_spawned.store(0, Ordering::Relaxed);
+        self.inner.loads_aborted.store(0, Ordering::Relaxed);
+    }
+
+    pub fn load_spawned(&self) {
+        self.inner.loads_spawned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn load_aborted(&self) {
+        self.inner.loads_aborted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a waker which counts its wakes before waking `inner`.
+    pub fn counting_waker(&self, inner: Waker) -> Waker {
+        waker(Arc::new(CountingWaker { inner, counters: self.inner.clone() }))
+    }
+
+    pub fn snapshot(
+        &self,
+        revision: Revision,
+        cache: Vec<NamespaceStats>,
+        commits: u64,
+    ) -> RevisionStats {
+        RevisionStats {
+            revision,
+            cache,
+            commits,
+            wakes: self.inner.wakes.load(Ordering::Relaxed),
+            loads_spawned: self.inner.loads_spawned.load(Ordering::Relaxed),
+            loads_aborted: self.inner.loads_aborted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct CountingWaker {
+    inner: Waker,
+    counters: Arc<Counters>,
+}
+
+impl ArcWake for CountingWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.counters.wakes.fetch_add(1, Ordering::Relaxed);
+        arc_self.inner.wake_by_ref();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cache, load, runtime::RunLoop, state};
+    use futures::{executor::LocalPool, future::pending};
+
+    #[test]
+    fn counts_per_revision() {
+        let mut rt = RunLoop::new(|| {
+            let (count, increment) = state(|| 0);
+            let doubled = cache(&*count, |c| c * 2);
+            let _ = load(&*count, |_| pending::<()>());
+            (doubled, increment)
+        });
+        let pool = LocalPool::new();
+        rt.set_task_executor(pool.spawner());
+
+        let (_, increment) = rt.run_once();
+        let stats = rt.stats();
+        assert_eq!(stats.cache_hits(), 0);
+        assert!(stats.cache_misses() > 0);
+        assert_eq!((stats.commits(), stats.wakes()), (1, 1), "load marked pending");
+        assert_eq!((stats.loads_spawned(), stats.loads_aborted()), (1, 0));
+
+        let misses = stats.cache_misses();
+        rt.run_once();
+        let stats = rt.stats();
+        assert_eq!(stats.cache_hits(), misses, "nothing changed");
+        assert_eq!((stats.cache_misses(), stats.evictions()), (0, 0));
+
+        increment.update(|c| Some(c + 1));
+        increment.update(|c| Some(c + 1));
+        let stats = rt.stats();
+        assert_eq!((stats.commits(), stats.wakes()), (2, 2), "counted until the next revision");
+
+        rt.run_once();
+        let stats = rt.stats();
+        assert_eq!(stats.commits(), 1, "load marked pending");
+        assert_eq!((stats.loads_spawned(), stats.loads_aborted()), (1, 1));
+        assert_eq!(stats.evictions(), 0, "replaced values aren't collected");
+    }
+}
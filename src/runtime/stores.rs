@@ -0,0 +1,71 @@
+use super::Var;
+use parking_lot::Mutex;
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    hash::{Hash, Hasher},
+    rc::Rc,
+    sync::Arc,
+};
+
+/// The state variables returned by [`crate::store`], one for each type. They
+/// are owned by the runtime rather than its cache, so they're never collected.
+#[derive(Clone, Default)]
+pub(crate) struct Stores {
+    inner: Rc<RefCell<HashMap<TypeId, Rc<dyn Any>>>>,
+}
+
+impl Stores {
+    /// Returns the store for `State`, creating it with `init` if it doesn't
+    /// exist yet.
+    pub fn get_or_insert_with<State: 'static>(
+        &self,
+        init: impl FnOnce() -> Arc<Mutex<Var<State>>>,
+    ) -> Arc<Mutex<Var<State>>> {
+        let existing = self.inner.borrow().get(&TypeId::of::<State>()).cloned();
+        let store = existing.unwrap_or_else(|| {
+            // initialize without borrowing in case the initializer uses other stores
+            let store: Rc<dyn Any> = Rc::new(init());
+            self.inner.borrow_mut().entry(TypeId::of::<State>()).or_insert(store).clone()
+        });
+        store.downcast_ref::<Arc<Mutex<Var<State>>>>().expect("stores are keyed by type").clone()
+    }
+}
+
+impl Debug for Stores {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Stores").field("len", &self.inner.borrow().len()).finish()
+    }
+}
+
+/// The slot of the callsite at which the store for a type is declared. Stores
+/// are told apart by their `TypeId`, but hashed by their type's name so that
+/// the stable ids of their callsites, which key persisted state, don't change
+/// between builds. Types with the same name share persisted values.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StoreSlot {
+    ty: TypeId,
+    name: &'static str,
+}
+
+impl StoreSlot {
+    pub fn of<State: 'static>() -> Self {
+        Self { ty: TypeId::of::<State>(), name: type_name::<State>() }
+    }
+}
+
+impl PartialEq for StoreSlot {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty == other.ty
+    }
+}
+impl Eq for StoreSlot {}
+
+impl Hash for StoreSlot {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        // equal types have equal names, so this agrees with `PartialEq`
+        self.name.hash(hasher);
+    }
+}
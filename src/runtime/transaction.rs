@@ -0,0 +1,112 @@
+use illicit::AsContext;
+use scopeguard::ScopeGuard;
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+    task::Waker,
+};
+
+/// Collects the commits made by [`crate::transaction`], deferring their
+/// visibility and their wakes until the transaction has finished.
+#[derive(Clone)]
+pub(crate) struct Transaction {
+    status: TxnStatus,
+    wakers: Rc<RefCell<Vec<Waker>>>,
+}
+
+impl Transaction {
+    /// Run `op` in a transaction, or in the current transaction if one is
+    /// already running.
+    pub fn run<R>(op: impl FnOnce() -> R) -> R {
+        if illicit::get::<Transaction>().is_ok() {
+            return op();
+        }
+
+        let status = TxnStatus(Arc::new(AtomicU8::new(RUNNING)));
+        let txn = Transaction { status: status.clone(), wakers: Default::default() };
+        let abort_on_panic = scopeguard::guard(status, |status| status.finish(ABORTED));
+        let ret = txn.clone().offer(op);
+
+        ScopeGuard::into_inner(abort_on_panic).finish(COMMITTED);
+        for waker in txn.wakers.replace(Vec::new()) {
+            waker.wake();
+        }
+        ret
+    }
+
+    /// Returns the current transaction, if any.
+    pub fn current() -> Option<Self> {
+        illicit::get::<Transaction>().ok().map(|txn| (*txn).clone())
+    }
+
+    /// Record a commit to a state variable which notifies `waker`, returning
+    /// the status the commit should wait for before becoming visible.
+    pub fn stage(&self, waker: &Waker) -> TxnStatus {
+        let mut wakers = self.wakers.borrow_mut();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.status.clone()
+    }
+}
+
+impl Debug for Transaction {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Transaction")
+            .field("status", &self.status)
+            .field("wakers", &self.wakers.borrow().len())
+            .finish()
+    }
+}
+
+const RUNNING: u8 = 0;
+const COMMITTED: u8 = 1;
+const ABORTED: u8 = 2;
+
+/// The progress of a transaction, shared with the state variables it commits
+/// to. Transactions which panic are aborted and their commits discarded.
+#[derive(Clone, Debug)]
+pub(crate) struct TxnStatus(Arc<AtomicU8>);
+
+impl TxnStatus {
+    /// Returns true if the transaction finished without panicking.
+    pub fn is_committed(&self) -> bool {
+        self.0.load(Ordering::Acquire) == COMMITTED
+    }
+
+    /// Returns true if the transaction panicked.
+    pub fn is_aborted(&self) -> bool {
+        self.0.load(Ordering::Acquire) == ABORTED
+    }
+
+    /// Returns true if a commit made within the transaction with `status`, or
+    /// outside of any transaction if it's `None`, has been or will be made
+    /// visible. Such commits are the only ones which are reported.
+    pub fn is_visible(status: Option<&Self>) -> bool {
+        match status {
+            Some(status) => status.is_committed(),
+            None => true,
+        }
+    }
+
+    /// Returns true if a commit made within the transaction with `status` has
+    /// been discarded because the transaction aborted.
+    pub fn is_discarded(status: Option<&Self>) -> bool {
+        matches!(status, Some(status) if status.is_aborted())
+    }
+
+    /// Returns true if `self` and `other` are the status of the same
+    /// transaction.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    fn finish(&self, status: u8) {
+        self.0.store(status, Ordering::Release);
+    }
+}
@@ -0,0 +1,160 @@
+use super::Var;
+use crate::Commit;
+use parking_lot::Mutex;
+use std::{collections::VecDeque, sync::Arc};
+
+/// The undo and redo stacks of a state variable declared with
+/// [`crate::history_state`]. Holds previous [`Commit`]s rather than copies of
+/// their values.
+pub(crate) struct UndoStack<State> {
+    capacity: usize,
+    past: VecDeque<Commit<State>>,
+    future: Vec<Commit<State>>,
+    /// The last commit made by [`UndoStack::commit`], which later commits
+    /// coalesce with until it is flushed.
+    open: Option<Commit<State>>,
+}
+
+impl<State> UndoStack<State> {
+    pub fn new(capacity: usize) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            capacity,
+            past: VecDeque::new(),
+            future: Vec::new(),
+            open: None,
+        }))
+    }
+
+    /// Change the number of steps which can be undone, dropping the oldest
+    /// steps if there are more than `capacity`.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.past.len() > capacity {
+            self.past.pop_front();
+        }
+    }
+
+    /// Enqueue a commit of `new` to `var`, recording its previous value so it
+    /// can be undone. Commits made before the previous one is flushed are
+    /// coalesced into a single step.
+    pub fn commit(&mut self, var: &mut Var<State>, new: State) {
+        let coalesce = match (var.uncommitted(), &self.open) {
+            (Some(pending), Some(open)) => Arc::ptr_eq(&pending.inner, &open.inner),
+            _ => false,
+        };
+        if !coalesce && self.capacity > 0 {
+            if self.past.len() == self.capacity {
+                self.past.pop_front();
+            }
+            self.past.push_back(var.latest_commit().clone());
+        }
+        self.future.clear();
+
+        var.enqueue_commit(new);
+        self.open = var.uncommitted().cloned();
+    }
+
+    /// Restore the value before the last undoable commit, returning false if
+    /// there wasn't one.
+    pub fn undo(&mut self, var: &mut Var<State>) -> bool {
+        if let Some(previous) = self.past.pop_back() {
+            self.future.push(var.latest_commit().clone());
+            self.restore(var, previous);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Restore the value before the last undo, returning false if there wasn't
+    /// one.
+    pub fn redo(&mut self, var: &mut Var<State>) -> bool {
+        if let Some(next) = self.future.pop() {
+            self.past.push_back(var.latest_commit().clone());
+            self.restore(var, next);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.past.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.future.is_empty()
+    }
+
+    fn restore(&mut self, var: &mut Var<State>, commit: Commit<State>) {
+        self.open = None;
+        var.enqueue(commit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{history_state, runtime::RunLoop};
+
+    #[test]
+    fn commits_between_revisions_coalesce() {
+        let mut rt = RunLoop::new(|| history_state(String::new, 10));
+
+        let (_, text) = rt.run_once();
+        text.update(|t| Some(format!("{}a", t)));
+        text.update(|t| Some(format!("{}b", t)));
+        let (_, text) = rt.run_once();
+        text.update(|t| Some(format!("{}c", t)));
+        assert_eq!(*rt.run_once().0, "abc");
+
+        let (_, text) = rt.run_once();
+        assert!(text.undo());
+        assert_eq!(*rt.run_once().0, "ab");
+        assert!(text.undo());
+        assert_eq!(*rt.run_once().0, "", "a and b were typed in one revision");
+        assert!(!text.can_undo());
+
+        assert!(text.redo());
+        text.update(|t| Some(format!("{}d", t)));
+        assert!(!text.can_redo(), "new commits clear the redo stack");
+        assert_eq!(*rt.run_once().0, "abd");
+        assert!(text.undo());
+        assert_eq!(*rt.run_once().0, "ab", "redone value wasn't coalesced");
+    }
+
+    #[test]
+    fn oldest_steps_are_dropped() {
+        let mut rt = RunLoop::new(|| history_state(|| 0, 2));
+        for i in 1..=3 {
+            rt.run_once().1.set(i);
+        }
+        let (current, count) = rt.run_once();
+        assert_eq!(*current, 3);
+
+        assert!(count.undo());
+        assert!(count.undo());
+        assert!(!count.undo(), "only two steps kept");
+        assert_eq!(*rt.run_once().0, 1);
+    }
+
+    #[test]
+    fn capacity_can_change() {
+        let mut rt = RunLoop::new({
+            let mut capacity = 5;
+            move || {
+                let ret = history_state(|| 0, capacity);
+                capacity = 1;
+                ret
+            }
+        });
+        for i in 1..=3 {
+            rt.run_once().1.set(i);
+        }
+        let (current, count) = rt.run_once();
+        assert_eq!(*current, 3);
+
+        assert!(count.undo());
+        assert!(!count.undo(), "older steps were dropped when the capacity shrank");
+        assert_eq!(*rt.run_once().0, 2);
+    }
+}
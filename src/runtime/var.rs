@@ -1,4 +1,11 @@
+use super::{
+    pending::PendingCommits,
+    recorder::History,
+    transaction::{Transaction, TxnStatus},
+    Reads, Revision,
+};
 use crate::{Commit, Key};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use parking_lot::Mutex;
 use std::{sync::Arc, task::Waker};
 
@@ -8,39 +15,166 @@ pub(crate) struct Var<State> {
     current: Commit<State>,
     id: topo::CallId,
     pending: Option<Commit<State>>,
+    staged: Option<(Commit<State>, TxnStatus)>,
     waker: Waker,
+    pending_commits: PendingCommits,
+    history: Option<History<State>>,
+    changes: Vec<UnboundedSender<Commit<State>>>,
+    collected: bool,
+}
+
+/// The handle to a [`Var`] which is stored in the cache. Ends the variable's
+/// streams of changes when it's garbage collected, even if [`Key`]s to it are
+/// still held elsewhere.
+pub(crate) struct CachedVar<State>(pub Arc<Mutex<Var<State>>>);
+
+impl<State> Drop for CachedVar<State> {
+    fn drop(&mut self) {
+        let mut var = self.0.lock();
+        // publish the last commit from a finished transaction before the streams end
+        var.settle_staged();
+        var.collected = true;
+        var.changes.clear();
+    }
 }
 
 impl<State> Var<State> {
-    pub fn new(id: topo::CallId, waker: Waker, inner: State) -> Arc<Mutex<Self>> {
+    pub fn new(
+        id: topo::CallId,
+        waker: Waker,
+        pending_commits: PendingCommits,
+        inner: State,
+    ) -> Arc<Mutex<Self>> {
         let current = Commit { id, inner: Arc::new(inner) };
-        Arc::new(Mutex::new(Var { id, current, waker, pending: None }))
+        Arc::new(Mutex::new(Var {
+            id,
+            current,
+            waker,
+            pending_commits,
+            pending: None,
+            staged: None,
+            history: None,
+            changes: Vec::new(),
+            collected: false,
+        }))
     }
 
     /// Attach this `Var` to its callsite, performing any pending commit and
-    /// returning the resulting latest commit.
-    pub fn root(var: Arc<Mutex<Self>>) -> (Commit<State>, Key<State>) {
+    /// returning the resulting latest commit. The read is recorded if made while
+    /// computing a [`crate::derived`] value.
+    pub fn root(var: Arc<Mutex<Self>>) -> (Commit<State>, Key<State>)
+    where
+        State: 'static,
+    {
         let (id, commit_at_root) = {
             let mut var = var.lock();
+            var.settle_staged();
             if let Some(pending) = var.pending.take() {
                 var.current = pending;
             }
             (var.id, var.current.clone())
         };
+        Reads::record(&var, &commit_at_root);
 
         (commit_at_root.clone(), Key { id, commit_at_root, var })
     }
 
-    /// Returns a reference to the latest value, pending or committed.
+    /// Make a staged commit pending if its transaction has finished, or discard
+    /// it if the transaction panicked.
+    fn settle_staged(&mut self) {
+        if let Some((staged, status)) = self.staged.take() {
+            if status.is_committed() {
+                self.publish(&staged);
+                self.pending = Some(staged);
+            } else if !status.is_aborted() {
+                self.staged = Some((staged, status));
+            }
+        }
+    }
+
+    /// Returns a stream of the commits this variable receives from now on,
+    /// which ends once it has been garbage collected.
+    pub fn changes(&mut self) -> UnboundedReceiver<Commit<State>> {
+        let (sender, changes) = unbounded();
+        if !self.collected {
+            self.changes.push(sender);
+        }
+        changes
+    }
+
+    /// Send `commit` to the streams of changes which are still being polled.
+    fn publish(&mut self, commit: &Commit<State>) {
+        self.changes.retain(|changes| changes.unbounded_send(commit.clone()).is_ok());
+    }
+
+    /// Returns a reference to the latest value, staged, pending or committed.
     pub fn latest(&self) -> &State {
-        &self.pending.as_ref().unwrap_or(&self.current)
+        self.latest_commit()
+    }
+
+    /// Returns the latest commit, staged, pending or committed.
+    pub fn latest_commit(&self) -> &Commit<State> {
+        self.uncommitted().unwrap_or(&self.current)
+    }
+
+    /// Returns the latest staged or pending commit, if any, which will be
+    /// flushed the next time this variable is rooted.
+    pub fn uncommitted(&self) -> Option<&Commit<State>> {
+        match &self.staged {
+            Some((staged, status)) if !status.is_aborted() => Some(staged),
+            _ => self.pending.as_ref(),
+        }
     }
 
     /// Initiate a commit to the state variable. The commit will actually
     /// complete asynchronously when the state variable is next rooted in a
     /// topological function, flushing the pending commit.
+    ///
+    /// Within a [`crate::transaction`] the commit is staged instead, and is not
+    /// flushed until the transaction has finished.
     pub fn enqueue_commit(&mut self, state: State) {
-        self.pending = Some(Commit { inner: Arc::new(state), id: self.id });
-        self.waker.wake_by_ref();
+        self.enqueue(Commit { inner: Arc::new(state), id: self.id });
+    }
+
+    /// Initiate a commit of an existing value, like one from an earlier commit.
+    /// See [`Var::enqueue_commit`].
+    pub fn enqueue(&mut self, commit: Commit<State>) {
+        let txn = Transaction::current().map(|txn| txn.stage(&self.waker));
+        // commits from transactions which abort are ignored when reported
+        if let Some(history) = &mut self.history {
+            history.record(&commit, txn.as_ref());
+        }
+        #[cfg(feature = "tracing-spans")]
+        tracing::trace!(id = ?self.id, state = std::any::type_name::<State>(), "commit");
+        self.pending_commits.push(self.id, txn.as_ref());
+        // a finished transaction's commit mustn't be replaced by one which may abort
+        self.settle_staged();
+        if let Some(status) = txn {
+            self.staged = Some((commit, status));
+        } else {
+            self.publish(&commit);
+            self.staged = None;
+            self.pending = Some(commit);
+            self.waker.wake_by_ref();
+        }
+    }
+
+    /// Record all future commits to this variable, starting from its current
+    /// value.
+    pub fn start_recording(&mut self, start: impl FnOnce(&Commit<State>) -> History<State>) {
+        self.history = Some(start(&self.current));
+    }
+
+    /// Enqueue a commit which restores the value this variable had at the start
+    /// of `revision`, if its commits have been recorded. The restored commit is
+    /// not itself recorded.
+    pub fn travel_to(&mut self, revision: Revision) {
+        if let Some(history) = &self.history {
+            let restored = history.as_of(revision);
+            self.publish(&restored);
+            self.staged = None;
+            self.pending = Some(restored);
+            self.waker.wake_by_ref();
+        }
     }
 }
@@ -0,0 +1,216 @@
+//! Thread-safe counterparts of the crate root's functions for use within a
+//! [`SendRuntime`].
+//!
+//! Each function here behaves like the function of the same name in the crate
+//! root, but stores its values in the [`SendRuntime`]'s synchronized cache and
+//! requires them to be `Send`. Calling these functions outside of a
+//! [`SendRuntime`] panics, as does calling the crate root's functions within
+//! one.
+//!
+//! [`SendRuntime`]: crate::runtime::SendRuntime
+
+use crate::{runtime::SendContext, Commit, Key};
+use std::{borrow::Borrow, future::Future, task::Poll};
+use topo::CallId;
+
+/// Cache the return of the `init` function. See [`crate::cache_with`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRunLoop, sync::cache_with};
+///
+/// let mut rt = SendRunLoop::new(|| cache_with(&(), |()| vec![1, 2, 3], |v| v.len()));
+/// let len = std::thread::spawn(move || rt.run_once()).join().unwrap();
+/// assert_eq!(len, 3);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache_with<Arg, Input, Output, Ret>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Output,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Ret
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Send + 'static,
+    Ret: Send + 'static,
+{
+    rt.cache.cache_with(&CallId::current(), arg, init, with)
+}
+
+/// Memoizes `init` at this callsite. See [`crate::cache`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRunLoop, sync::cache};
+/// use std::sync::{
+///     atomic::{AtomicU64, Ordering},
+///     Arc,
+/// };
+///
+/// let num_created = Arc::new(AtomicU64::new(0));
+/// let created = num_created.clone();
+/// let mut rt = SendRunLoop::new(move || {
+///     cache(&(), |()| created.fetch_add(1, Ordering::Relaxed));
+/// });
+///
+/// rt.run_once();
+/// let mut rt = std::thread::spawn(move || {
+///     rt.run_once();
+///     rt
+/// })
+/// .join()
+/// .unwrap();
+/// rt.run_once();
+/// assert_eq!(num_created.load(Ordering::Relaxed), 1);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache<Arg, Input, Output>(arg: &Arg, init: impl FnOnce(&Input) -> Output) -> Output
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Clone + Send + 'static,
+{
+    rt.cache.cache(&CallId::current(), arg, init)
+}
+
+/// Runs `init` once per [`topo::CallId`]. See [`crate::once`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRunLoop, sync::once};
+///
+/// let mut rt = SendRunLoop::new(|| once(|| String::from("hello")));
+/// assert_eq!(rt.run_once(), "hello");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn once<Output>(init: impl FnOnce() -> Output) -> Output
+where
+    Output: Clone + Send + 'static,
+{
+    rt.cache.cache(&CallId::current(), &(), |()| init())
+}
+
+/// Root a state variable at this callsite, returning a [`Key`] to the state
+/// variable. See [`crate::state`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRunLoop, sync::state};
+///
+/// let mut rt = SendRunLoop::new(|| state(|| 0u64));
+/// let (_, key) = rt.run_once();
+/// std::thread::spawn(move || key.set(1)).join().unwrap();
+///
+/// let (commit, _) = rt.run_once();
+/// assert_eq!(*commit, 1);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn state<Output>(init: impl FnOnce() -> Output) -> (Commit<Output>, Key<Output>)
+where
+    Output: Send + Sync + 'static,
+{
+    rt.cache_state(&CallId::current(), &(), |_| init())
+}
+
+/// Root a state variable at this callsite, re-initializing it whenever `arg`
+/// changes. See [`crate::cache_state`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRuntime, sync::cache_state};
+///
+/// let root = |epoch: u8| cache_state(&epoch, |e| *e * 10);
+/// let mut rt = SendRuntime::new();
+///
+/// let (_, key) = rt.run_once(|| root(0));
+/// key.set(5);
+/// assert_eq!(*rt.run_once(|| root(0)).0, 5);
+/// assert_eq!(*rt.run_once(|| root(1)).0, 10, "reinitialized");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache_state<Arg, Input, Output>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Output,
+) -> (Commit<Output>, Key<Output>)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Send + Sync + 'static,
+{
+    rt.cache_state(&CallId::current(), arg, init)
+}
+
+/// Load a value from the future returned by `init` whenever `capture` changes,
+/// returning the result of calling `with` with the loaded value. See
+/// [`crate::load_with`].
+///
+/// # Example
+///
+/// ```
+/// use futures::{executor::ThreadPool, future::ready};
+/// use moxie::{runtime::SendRunLoop, sync::load_with};
+///
+/// let mut rt = SendRunLoop::new(|| load_with(&(), |()| ready(21), |n| n * 2));
+/// rt.set_task_executor(ThreadPool::new().unwrap());
+///
+/// while rt.run_once().is_pending() {
+///     std::thread::yield_now();
+/// }
+/// assert_eq!(rt.run_once(), std::task::Poll::Ready(42));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load_with<Arg, Input, Fut, Output, Ret>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Send + Sync + 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), capture, init, with)
+}
+
+/// Calls [`load_with`] but never re-initializes the loading future. See
+/// [`crate::load_once`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load_once<Fut, Output>(init: impl FnOnce() -> Fut) -> Poll<Output>
+where
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+{
+    rt.load_with(&CallId::current(), &(), |()| init(), Clone::clone)
+}
+
+/// Calls [`load_with`], never calls `with`, and clones the loaded value. See
+/// [`crate::load`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load<Arg, Input, Fut, Output>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+) -> Poll<Output>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+{
+    rt.load_with(&CallId::current(), capture, init, Clone::clone)
+}
@@ -1,9 +1,21 @@
 //! Utilities for testing moxie-based programs.
 
-use futures::task::ArcWake;
-use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+use crate::runtime::{Clock, RunLoop};
+use futures::{
+    channel::oneshot,
+    executor::LocalPool,
+    future::{pending, ready, LocalBoxFuture},
+    task::{waker, ArcWake},
+    FutureExt,
+};
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 /// A value which keeps track of how many times it's been cloned. Useful for
@@ -46,3 +58,281 @@ impl ArcWake for BoolWaker {
         arc_self.0.store(true, Ordering::Relaxed);
     }
 }
+
+/// A [`RunLoop`] bundled with an executor for its tasks, a [`VirtualClock`],
+/// and a waker which counts how often state changes woke it, for
+/// deterministic tests of moxie functions.
+///
+/// Tasks spawned by the runtime only run when the test calls
+/// [`TestLoop::run_tasks`] or [`TestLoop::advance`], and time only passes
+/// when the test calls [`TestLoop::advance`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{load, testing::{Resolver, TestLoop}};
+/// use std::task::Poll;
+///
+/// let name = Resolver::new();
+/// let requested = name.clone();
+/// let mut test = TestLoop::new(move || load(&(), |()| requested.future()));
+///
+/// assert_eq!(test.run_once(), Poll::Pending);
+/// test.run_tasks();
+/// test.assert_woken(1); // loads reset their result to pending on the first revision
+///
+/// assert!(name.resolve("alice"));
+/// test.run_tasks();
+/// test.assert_woken(1);
+/// assert_eq!(test.run_once(), Poll::Ready("alice"));
+/// test.assert_no_pending_commits();
+/// ```
+pub struct TestLoop<Root> {
+    rt: RunLoop<Root>,
+    pool: LocalPool,
+    clock: VirtualClock,
+    wakes: Arc<CountingWaker>,
+}
+
+impl<Root, Out> TestLoop<Root>
+where
+    Root: FnMut() -> Out + Unpin,
+{
+    /// Returns a new test loop which runs `root` in each revision.
+    pub fn new(root: Root) -> Self {
+        let mut rt = RunLoop::new(root);
+        let pool = LocalPool::new();
+        let clock = VirtualClock::new();
+        let wakes = Arc::new(CountingWaker::default());
+        rt.set_task_executor(pool.spawner());
+        rt.set_clock(clock.clone());
+        rt.set_state_change_waker(waker(wakes.clone()));
+        Self { rt, pool, clock, wakes }
+    }
+
+    /// Runs a single revision, returning the root function's output.
+    pub fn run_once(&mut self) -> Out {
+        self.rt.run_once()
+    }
+
+    /// Runs the tasks spawned by the runtime until none of them can make
+    /// progress, for example until each [`Resolver`] future they await has
+    /// been resolved.
+    pub fn run_tasks(&mut self) {
+        self.pool.run_until_stalled();
+    }
+
+    /// Moves the loop's clock forward by `by`, running tasks as each timer
+    /// completes so that timers they start in the meantime complete too.
+    pub fn advance(&mut self, by: Duration) {
+        let until = self.clock.now() + by;
+        self.run_tasks();
+        while let Some(deadline) = self.clock.next_deadline().filter(|d| *d <= until) {
+            self.clock.advance(deadline - self.clock.now());
+            self.run_tasks();
+        }
+        self.clock.advance(until - self.clock.now());
+    }
+
+    /// Returns the loop's clock.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Returns the underlying [`RunLoop`], for example to set other
+    /// executors.
+    pub fn run_loop(&mut self) -> &mut RunLoop<Root> {
+        &mut self.rt
+    }
+
+    /// Returns the number of times the runtime has been woken since the last
+    /// call to [`TestLoop::assert_woken`], or since the loop was created.
+    pub fn wakes(&self) -> u64 {
+        self.wakes.0.load(Ordering::Relaxed)
+    }
+
+    /// Asserts that the runtime was woken `times` times since the last call to
+    /// this method, or since the loop was created, and resets the count.
+    #[track_caller]
+    pub fn assert_woken(&mut self, times: u64) {
+        let wakes = self.wakes.0.swap(0, Ordering::Relaxed);
+        assert_eq!(wakes, times, "expected {} wakes, runtime was woken {} times", times, wakes);
+    }
+
+    /// Asserts that no state variables have received commits since the start
+    /// of the latest revision, so running another would observe no changes.
+    #[track_caller]
+    pub fn assert_no_pending_commits(&self) {
+        let committing = self.rt.committing();
+        assert!(
+            committing.is_empty(),
+            "expected no pending commits, found commits to {:?}",
+            committing
+        );
+    }
+}
+
+#[derive(Default)]
+struct CountingWaker(AtomicU64);
+
+impl ArcWake for CountingWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Creates futures which are completed by the test, for example to control
+/// when the futures passed to [`crate::load`] finish. Clones resolve the same
+/// futures.
+pub struct Resolver<T> {
+    sender: Rc<RefCell<Option<oneshot::Sender<T>>>>,
+    requested: Rc<Cell<u64>>,
+}
+
+impl<T> Resolver<T>
+where
+    T: 'static,
+{
+    /// Returns a new resolver without any waiting futures.
+    pub fn new() -> Self {
+        Self { sender: Default::default(), requested: Default::default() }
+    }
+
+    /// Returns a future which completes with the value passed to the next
+    /// call to [`Resolver::resolve`]. Futures returned by previous calls will
+    /// never complete.
+    pub fn future(&self) -> LocalBoxFuture<'static, T> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender.replace(Some(sender));
+        self.requested.set(self.requested.get() + 1);
+        async move {
+            match receiver.await {
+                Ok(value) => value,
+                Err(_) => pending().await,
+            }
+        }
+        .boxed_local()
+    }
+
+    /// Completes the latest future with `value`. Returns false if there was no
+    /// future still waiting for a value.
+    pub fn resolve(&self, value: T) -> bool {
+        match self.sender.borrow_mut().take() {
+            Some(sender) => sender.send(value).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Returns the number of futures returned by [`Resolver::future`] for this
+    /// resolver and its clones, for example to check how often a load was
+    /// started.
+    pub fn requested(&self) -> u64 {
+        self.requested.get()
+    }
+}
+
+impl<T> Clone for Resolver<T> {
+    fn clone(&self) -> Self {
+        Self { sender: self.sender.clone(), requested: self.requested.clone() }
+    }
+}
+
+impl<T> Default for Resolver<T>
+where
+    T: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A [`Clock`] whose time only passes when [`VirtualClock::advance`] is called.
+/// Clones share the same time and timers.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::Clock, testing::VirtualClock};
+/// use std::time::Duration;
+///
+/// # futures::executor::block_on(async {
+/// let clock = VirtualClock::new();
+/// let mut sleep = clock.sleep(Duration::from_secs(1));
+/// assert_eq!(clock.next_deadline(), Some(Duration::from_secs(1)));
+///
+/// clock.advance(Duration::from_millis(999));
+/// assert!(futures::poll!(&mut sleep).is_pending());
+///
+/// clock.advance(Duration::from_millis(1));
+/// sleep.await;
+/// assert_eq!(clock.now(), Duration::from_secs(1));
+/// assert_eq!(clock.next_deadline(), None);
+/// # });
+/// ```
+#[derive(Clone, Default)]
+pub struct VirtualClock {
+    inner: Rc<RefCell<VirtualTime>>,
+}
+
+#[derive(Default)]
+struct VirtualTime {
+    now: Duration,
+    timers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+impl VirtualClock {
+    /// Returns a new clock at time zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the time at which the earliest pending sleep completes.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        let mut inner = self.inner.borrow_mut();
+        inner.timers.retain(|(_, timer)| !timer.is_canceled());
+        inner.timers.iter().map(|(deadline, _)| *deadline).min()
+    }
+
+    /// Moves the clock forward by `by`, completing each sleep whose deadline
+    /// has been reached in the order of their deadlines.
+    pub fn advance(&self, by: Duration) {
+        let mut inner = self.inner.borrow_mut();
+        inner.now += by;
+        let now = inner.now;
+
+        let (mut expired, pending) =
+            inner.timers.drain(..).partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        inner.timers = pending;
+        drop(inner);
+
+        expired.sort_by_key(|(deadline, _)| *deadline);
+        for (_, timer) in expired {
+            timer.send(()).ok();
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    /// Returns the time which has passed since the clock was created.
+    fn now(&self) -> Duration {
+        self.inner.borrow().now
+    }
+
+    fn sleep(&self, duration: Duration) -> LocalBoxFuture<'static, ()> {
+        if duration == Duration::from_secs(0) {
+            return ready(()).boxed_local();
+        }
+
+        let (timer, fired) = oneshot::channel();
+        let mut inner = self.inner.borrow_mut();
+        let deadline = inner.now + duration;
+        inner.timers.push((deadline, timer));
+        async move {
+            if fired.await.is_err() {
+                // the clock was dropped, so time will never pass
+                pending::<()>().await;
+            }
+        }
+        .boxed_local()
+    }
+}
@@ -74,6 +74,12 @@
 //!
 //! Internally, slots are interned in a global [`dyn-cache`].
 //!
+//! # Tracing
+//!
+//! With the `tracing` feature enabled, each call enters a `TRACE`-level
+//! [`tracing`](https://docs.rs/tracing) span named `call` which records the
+//! `file` and `line` of its callsite and the index of its interned `slot`.
+//!
 //! [Incremental Computing]: https://en.wikipedia.org/wiki/Incremental_computing
 //! [caching problem]: https://en.wikipedia.org/wiki/Cache_(computing)
 
@@ -132,8 +138,15 @@
 #[doc(inline)]
 pub use topo_macro::nested;
 
+#[cfg(feature = "stable-id")]
+use fnv::FnvHasher;
 use slot::{OpaqueSlot, Slot};
-use std::{borrow::Borrow, cell::RefCell, hash::Hash, panic::Location};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    panic::Location,
+};
 
 mod slot;
 
@@ -188,7 +201,7 @@ where
 
     let callsite = Callsite::here();
     let count = CallCount(callsite.current_count());
-    Scope::with_current(|p| p.make_child(callsite, &count)).enter(op)
+    Scope::with_current(|p| p.make_child(callsite, &count)).enter(|| in_span(op))
 }
 
 /// Calls the provided function as a child of [`CallId::current`], using `slot`
@@ -265,7 +278,19 @@ where
     Q: Eq + Hash + ToOwned<Owned = S> + ?Sized,
     S: Borrow<Q> + Eq + Hash + Send + 'static,
 {
-    Scope::with_current(|p| p.make_child(Callsite::here(), slot)).enter(op)
+    Scope::with_current(|p| p.make_child(Callsite::here(), slot)).enter(|| in_span(op))
+}
+
+/// Runs `op` within a `tracing` span for the current [`CallId`] if the
+/// `tracing` feature is enabled.
+#[inline(always)]
+fn in_span<F, R>(op: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    #[cfg(feature = "tracing")]
+    let _span = CallId::current().span().entered();
+    op()
 }
 
 /// Calls the provided function as the root of a new call tree, ignoring the
@@ -394,6 +419,33 @@ where
 /// # Ok(()) }
 /// ```
 ///
+/// # Stable identifiers
+///
+/// `CallId`s can only be compared within a single process, because they are
+/// derived from the addresses of source locations and from slots which are
+/// interned in the order they're first seen. With the `stable-id` feature
+/// enabled, [`CallId::stable_id`] returns a hash of the same chain of callsites
+/// and slots which can be persisted and compared across executions of the same
+/// program, including builds with other Rust releases or for other targets.
+///
+/// Callsites are hashed by their file, line, and column, so any edit which
+/// moves a call within its file changes the stable ids of that call and of all
+/// calls nested within it. Values persisted under the old ids won't be found
+/// by the edited program.
+///
+/// ```
+/// # #[cfg(feature = "stable-id")] {
+/// use topo::{call_in_slot, root, CallId};
+///
+/// let first = root(|| call_in_slot("foo", CallId::current));
+/// let second = root(|| call_in_slot("foo", CallId::current));
+/// let other = root(|| call_in_slot("bar", CallId::current));
+///
+/// assert_eq!(first.stable_id(), second.stable_id());
+/// assert_ne!(first.stable_id(), other.stable_id());
+/// # }
+/// ```
+///
 /// [`nested`]: `crate::nested`
 /// [`call`]: `crate::call`
 /// [`call_in_slot`]: `crate::call_in_slot`
@@ -403,6 +455,8 @@ pub struct CallId {
     callsite: Callsite,
     parent: Slot<CallId>,
     slot: OpaqueSlot,
+    #[cfg(feature = "stable-id")]
+    stable: u64,
 }
 
 impl CallId {
@@ -412,6 +466,8 @@ impl CallId {
             callsite: Callsite::here(),
             parent: Slot::fake(),
             slot: Slot::<String>::fake().into(),
+            #[cfg(feature = "stable-id")]
+            stable: 0,
         }
     }
 
@@ -420,20 +476,151 @@ impl CallId {
         Scope::with_current(|current| current.id)
     }
 
+    /// Returns a hash of this `CallId`'s callsites and slots which does not
+    /// depend on the state of the current process. The same chain of calls will
+    /// produce the same value when run again by the same program, as long as
+    /// the file, line, and column of each callsite and the values written by
+    /// the slots' `Hash` implementations are unchanged. The hash doesn't depend
+    /// on the slots' types.
+    ///
+    /// Unlike `CallId`s themselves these values are not guaranteed to be
+    /// unique, but collisions are very unlikely.
+    ///
+    /// Requires the `stable-id` feature, so that other users don't pay for
+    /// hashing every call.
+    #[cfg(feature = "stable-id")]
+    pub fn stable_id(&self) -> u64 {
+        self.stable
+    }
+
+    /// Returns a span recording this call's source location and slot.
+    #[cfg(feature = "tracing")]
+    fn span(&self) -> tracing::Span {
+        tracing::trace_span!(
+            "call",
+            file = self.callsite.location.file(),
+            line = self.callsite.location.line(),
+            slot = self.slot.index(),
+        )
+    }
+
     pub(crate) fn child<Q, S>(&self, callsite: Callsite, slot: &Q) -> Self
     where
         Q: Eq + Hash + ToOwned<Owned = S> + ?Sized,
         S: Borrow<Q> + Eq + Hash + Send + 'static,
     {
-        Self { callsite, parent: Slot::make(self), slot: Slot::make(slot).into() }
+        Self {
+            callsite,
+            parent: Slot::make(self),
+            slot: Slot::make(slot).into(),
+            #[cfg(feature = "stable-id")]
+            stable: stable_hash(
+                self.stable,
+                callsite.location.file(),
+                callsite.location.line(),
+                callsite.location.column(),
+                slot,
+            ),
+        }
+    }
+}
+
+/// Hashes a call's parent, source location, and slot with FNV-1a, whose output
+/// is specified and doesn't depend on the Rust release, unlike std's
+/// `DefaultHasher`. See [`StableHasher`] for how the slot's integers are
+/// written.
+#[cfg(feature = "stable-id")]
+fn stable_hash<Q>(parent: u64, file: &str, line: u32, column: u32, slot: &Q) -> u64
+where
+    Q: Hash + ?Sized,
+{
+    let mut hasher = StableHasher::default();
+    hasher.write_u64(parent);
+    hasher.write(file.as_bytes());
+    hasher.write_u32(line);
+    hasher.write_u32(column);
+    slot.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A [`FnvHasher`] which writes integers in little-endian byte order and widens
+/// `usize` and `isize` to 64 bits, so that the result doesn't depend on the
+/// target. Slots whose `Hash` implementations write bytes which depend on the
+/// target, such as the bytes of a pointer, still produce target-specific
+/// hashes.
+#[cfg(feature = "stable-id")]
+#[derive(Default)]
+struct StableHasher(FnvHasher);
+
+#[cfg(feature = "stable-id")]
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64);
     }
 }
 
 /// A value unique to the source location where it is created.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug)]
 struct Callsite {
-    location: usize,
+    location: &'static Location<'static>,
+}
+
+impl Hash for Callsite {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.addr().hash(hasher);
+    }
+}
+
+impl PartialEq for Callsite {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
 }
+impl Eq for Callsite {}
 
 impl Callsite {
     /// Constructs a callsite whose value is unique to the source location at
@@ -455,14 +642,17 @@ impl Callsite {
             }
         })
     }
+
+    /// The pointer value for a given location is enough to differentiate it
+    /// from all others.
+    fn addr(self) -> usize {
+        self.location as *const _ as usize
+    }
 }
 
 impl From<&'static Location<'static>> for Callsite {
     fn from(location: &'static Location<'static>) -> Self {
-        Self {
-            // the pointer value for a given location is enough to differentiate it from all others
-            location: location as *const _ as usize,
-        }
+        Self { location }
     }
 }
 
@@ -533,6 +723,20 @@ mod tests {
     use crate::{call, root};
     use std::{collections::HashSet, sync::mpsc::channel, thread};
 
+    #[cfg(feature = "stable-id")]
+    #[test]
+    fn stable_hashes_are_pinned() {
+        // changing these values invalidates every persisted stable id
+        let foo = stable_hash(0, "src/lib.rs", 10, 5, "foo");
+        assert_eq!(foo, 0x8829e217a393d618);
+        assert_eq!(stable_hash(foo, "src/lib.rs", 12, 9, &3u32), 0x1f918fe028e26788);
+        assert_eq!(
+            stable_hash(foo, "src/lib.rs", 12, 9, &3usize),
+            stable_hash(foo, "src/lib.rs", 12, 9, &3u64),
+            "usize is hashed the same on every target"
+        );
+    }
+
     #[test]
     fn alternating_in_a_loop() {
         call(|| {
@@ -123,6 +123,14 @@ pub(crate) struct OpaqueSlot {
     index: u32,
 }
 
+impl OpaqueSlot {
+    /// The index of the slot's value among the interned values of its type.
+    #[cfg(feature = "tracing")]
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
 impl<T: 'static> From<Slot<T>> for OpaqueSlot {
     fn from(token: Slot<T>) -> Self {
         OpaqueSlot { index: token.index, ty: TypeId::of::<T>() }